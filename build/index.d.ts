@@ -1,4 +1,4 @@
-import { Config, Menu, MenuItem, MenuItemConstructorOptions, Theme } from "../lib";
+import { CloseReason, Config, ColorFormatOptions, Diagnostic, FileOptions, InputSource, ItemPatch, ItemsOptions, Menu, MenuOperation, Modifiers, MenuItem, MenuItemConstructorOptions, PartialConfig, SystemTheme, ThemeName } from "../lib";
 
 declare namespace PopupMenu {
 
@@ -12,20 +12,88 @@ declare namespace PopupMenu {
         submenu: Menu;
         name: string;
         readonly uuid:number;
+        items?: PopupMenuItem[];
     };
 
-    function getDefaultConfig(): Config;
-    function buildFromTemplate(menuWindowHandle:number, template:MenuItemConstructorOptions[]): number;
-    function buildFromTemplateWithTheme(menuWindowHandle:number, template:MenuItemConstructorOptions[], theme:Theme): number;
-    function buildFromTemplateWithConfig(menuWindowHandle:number, template:MenuItemConstructorOptions[], config:Config): number;
-    function popup(menuWindowHandle:number, x:number, y:number): Promise<PopupMenuItem>;
-    function items(menuWindowHandle:number): PopupMenuItem[];
-    function remove(menuWindowHandle:number, item:MenuItem): void;
-    function removeAt(menuWindowHandle:number, index:number): void;
-    function append(menuWindowHandle:number, item:MenuItem): void;
-    function insert(menuWindowHandle:number, index:number, item:MenuItem): void;
-    function setTheme(menuWindowHandle:number, theme:Theme): void;
-    function getMenuItemById(menuWindowHandle:number, id:string): PopupMenuItem | void;
+    /**
+     * Boxed native handle. The menu is destroyed when this object is garbage collected.
+     */
+    interface MenuBox {
+        readonly __menuBox: unique symbol;
+    }
+
+    type MenuHandle = number | MenuBox;
+
+    type BuildOptions = {
+        boxed?: boolean;
+        strict?: boolean;
+    };
+
+    type BuildMenuOptions = BuildOptions & {
+        parent: number | Buffer;
+        template: MenuItemConstructorOptions[];
+        theme?: ThemeName;
+        config?: PartialConfig;
+    };
+
+    type NativeReloadEvent = { type: "reloaded" } | { type: "reloadFailed"; error: Error };
+
+    type FileBuildOptions = Omit<FileOptions, "handlers" | "onReload"> & {
+        boxed?: boolean;
+        onReload?: (event: NativeReloadEvent) => void;
+    };
+
+    type NativeMenuOperation = Exclude<MenuOperation, { op: "updateItem" }> | { op: "updateItem"; id: string; patch: Omit<ItemPatch, "click"> };
+
+    type PopupResult = Partial<PopupMenuItem> & {
+        closeReason: CloseReason;
+        path: string[];
+        modifiers: Modifiers;
+        input: InputSource | null;
+        cancelled?: true;
+        reason?: string;
+    };
+
+    function getDefaultConfig(options?:ColorFormatOptions): Config;
+    function getSystemTheme(): SystemTheme;
+    function onSystemThemeChanged(callback:(theme:SystemTheme) => void): number;
+    function offSystemThemeChanged(id:number): boolean;
+    function validateTemplate(template:MenuItemConstructorOptions[]): Diagnostic[];
+    function validateConfig(config:PartialConfig): Diagnostic[];
+    function registerTheme(name:string, config:PartialConfig, options?:{ strict?: boolean }): void;
+    function listThemes(): string[];
+    function buildMenu(options:BuildMenuOptions & { boxed: true }): MenuBox;
+    function buildMenu(options:BuildMenuOptions): number;
+    function buildFromTemplate(menuWindowHandle:number | Buffer, template:MenuItemConstructorOptions[]): number;
+    function buildFromTemplate(menuWindowHandle:number | Buffer, template:MenuItemConstructorOptions[], options:BuildOptions & { boxed: true }): MenuBox;
+    function buildFromTemplateWithTheme(menuWindowHandle:number | Buffer, template:MenuItemConstructorOptions[], theme:ThemeName): number;
+    function buildFromTemplateWithTheme(menuWindowHandle:number | Buffer, template:MenuItemConstructorOptions[], theme:ThemeName, options:BuildOptions & { boxed: true }): MenuBox;
+    function buildFromTemplateWithConfig(menuWindowHandle:number | Buffer, template:MenuItemConstructorOptions[], config:PartialConfig, options?:BuildOptions & { boxed?: false }): number;
+    function buildFromTemplateWithConfig(menuWindowHandle:number | Buffer, template:MenuItemConstructorOptions[], config:PartialConfig, options:BuildOptions & { boxed: true }): MenuBox;
+    function buildFromFile(menuWindowHandle:number | Buffer, path:string, options?:FileBuildOptions & { boxed?: false }): number;
+    function buildFromFile(menuWindowHandle:number | Buffer, path:string, options:FileBuildOptions & { boxed: true }): MenuBox;
+    function popup(menuWindowHandle:MenuHandle, x:number, y:number): Promise<PopupResult>;
+    function close(menuWindowHandle:MenuHandle, reason?:string): boolean;
+    function destroy(menuWindowHandle:MenuHandle): void;
+    function getHandle(menuWindowHandle:MenuHandle): number;
+    function getNativeHandle(menuWindowHandle:MenuHandle): bigint;
+    function items(menuWindowHandle:MenuHandle, options?:ItemsOptions): PopupMenuItem[];
+    function toTemplate(menuWindowHandle:MenuHandle): MenuItemConstructorOptions[];
+    function remove(menuWindowHandle:MenuHandle, item:MenuItem): void;
+    function removeAt(menuWindowHandle:MenuHandle, index:number): void;
+    function append(menuWindowHandle:MenuHandle, item:MenuItem | MenuItemConstructorOptions): number | void;
+    function insert(menuWindowHandle:MenuHandle, index:number, item:MenuItem | MenuItemConstructorOptions): number | void;
+    function removeById(menuWindowHandle:MenuHandle, id:string): void;
+    function insertBefore(menuWindowHandle:MenuHandle, id:string, item:MenuItem | MenuItemConstructorOptions): number | void;
+    function insertAfter(menuWindowHandle:MenuHandle, id:string, item:MenuItem | MenuItemConstructorOptions): number | void;
+    function moveItem(menuWindowHandle:MenuHandle, id:string, targetParentId:string | null, index:number): void;
+    function setTheme(menuWindowHandle:MenuHandle, theme:ThemeName): void;
+    function setConfig(menuWindowHandle:MenuHandle, config:PartialConfig, options?:{ strict?: boolean }): void;
+    function getConfig(menuWindowHandle:MenuHandle, options?:ColorFormatOptions): Config;
+    function getMenuItemById(menuWindowHandle:MenuHandle, id:string): PopupMenuItem | void;
+    function updateItem(menuWindowHandle:MenuHandle, id:string, patch:Omit<ItemPatch, "click">): PopupMenuItem;
+    function setTemplate(menuWindowHandle:MenuHandle, template:MenuItemConstructorOptions[]): void;
+    function batch(menuWindowHandle:MenuHandle, ops:NativeMenuOperation[]): (number | PopupMenuItem | void)[];
 }
 
 export = PopupMenu;
\ No newline at end of file
@@ -1,4 +1,19 @@
 "use strict";
+var __extends = (this && this.__extends) || (function () {
+    var extendStatics = function (d, b) {
+        extendStatics = Object.setPrototypeOf ||
+            ({ __proto__: [] } instanceof Array && function (d, b) { d.__proto__ = b; }) ||
+            function (d, b) { for (var p in b) if (Object.prototype.hasOwnProperty.call(b, p)) d[p] = b[p]; };
+        return extendStatics(d, b);
+    };
+    return function (d, b) {
+        if (typeof b !== "function" && b !== null)
+            throw new TypeError("Class extends value " + String(b) + " is not a constructor or null");
+        extendStatics(d, b);
+        function __() { this.constructor = d; }
+        d.prototype = b === null ? Object.create(b) : (__.prototype = b.prototype, new __());
+    };
+})();
 var __assign = (this && this.__assign) || function () {
     __assign = Object.assign || function(t) {
         for (var s, i = 1, n = arguments.length; i < n; i++) {
@@ -69,17 +84,173 @@ var __generator = (this && this.__generator) || function (thisArg, body) {
         if (op[0] & 5) throw op[1]; return { value: op[0] ? op[1] : void 0, done: true };
     }
 };
+var __rest = (this && this.__rest) || function (s, e) {
+    var t = {};
+    for (var p in s) if (Object.prototype.hasOwnProperty.call(s, p) && e.indexOf(p) < 0)
+        t[p] = s[p];
+    if (s != null && typeof Object.getOwnPropertySymbols === "function")
+        for (var i = 0, p = Object.getOwnPropertySymbols(s); i < p.length; i++) {
+            if (e.indexOf(p[i]) < 0 && Object.prototype.propertyIsEnumerable.call(s, p[i]))
+                t[p[i]] = s[p[i]];
+        }
+    return t;
+};
 Object.defineProperty(exports, "__esModule", { value: true });
-exports.Menu = exports.getDefaultConfig = void 0;
+exports.Menu = exports.listThemes = exports.registerTheme = exports.onSystemThemeChanged = exports.getSystemTheme = exports.validateConfig = exports.validateTemplate = exports.getDefaultConfig = exports.InvalidArgumentError = exports.BackendFailureError = exports.InvalidConfigError = exports.InvalidTemplateError = exports.MenuBusyError = exports.UnknownHandleError = exports.MenuError = void 0;
 var PopupMenu = __importStar(require("../build/index"));
+var MenuError = /** @class */ (function (_super) {
+    __extends(MenuError, _super);
+    function MenuError(message, code) {
+        var _newTarget = this.constructor;
+        var _this = _super.call(this, message) || this;
+        _this.diagnostics = [];
+        Object.setPrototypeOf(_this, _newTarget.prototype);
+        _this.name = "MenuError";
+        _this.code = code;
+        return _this;
+    }
+    return MenuError;
+}(Error));
+exports.MenuError = MenuError;
+var UnknownHandleError = /** @class */ (function (_super) {
+    __extends(UnknownHandleError, _super);
+    function UnknownHandleError(message) {
+        var _this = _super.call(this, message, "ERR_UNKNOWN_HANDLE") || this;
+        _this.name = "UnknownHandleError";
+        return _this;
+    }
+    return UnknownHandleError;
+}(MenuError));
+exports.UnknownHandleError = UnknownHandleError;
+var MenuBusyError = /** @class */ (function (_super) {
+    __extends(MenuBusyError, _super);
+    function MenuBusyError(message) {
+        var _this = _super.call(this, message, "ERR_MENU_BUSY") || this;
+        _this.name = "MenuBusyError";
+        return _this;
+    }
+    return MenuBusyError;
+}(MenuError));
+exports.MenuBusyError = MenuBusyError;
+var InvalidTemplateError = /** @class */ (function (_super) {
+    __extends(InvalidTemplateError, _super);
+    function InvalidTemplateError(message) {
+        var _this = _super.call(this, message, "ERR_INVALID_TEMPLATE") || this;
+        _this.name = "InvalidTemplateError";
+        return _this;
+    }
+    return InvalidTemplateError;
+}(MenuError));
+exports.InvalidTemplateError = InvalidTemplateError;
+var InvalidConfigError = /** @class */ (function (_super) {
+    __extends(InvalidConfigError, _super);
+    function InvalidConfigError(message) {
+        var _this = _super.call(this, message, "ERR_INVALID_CONFIG") || this;
+        _this.name = "InvalidConfigError";
+        return _this;
+    }
+    return InvalidConfigError;
+}(MenuError));
+exports.InvalidConfigError = InvalidConfigError;
+var BackendFailureError = /** @class */ (function (_super) {
+    __extends(BackendFailureError, _super);
+    function BackendFailureError(message) {
+        var _this = _super.call(this, message, "ERR_BACKEND_FAILURE") || this;
+        _this.name = "BackendFailureError";
+        return _this;
+    }
+    return BackendFailureError;
+}(MenuError));
+exports.BackendFailureError = BackendFailureError;
+var InvalidArgumentError = /** @class */ (function (_super) {
+    __extends(InvalidArgumentError, _super);
+    function InvalidArgumentError(message) {
+        var _this = _super.call(this, message, "ERR_INVALID_ARGUMENT") || this;
+        _this.name = "InvalidArgumentError";
+        return _this;
+    }
+    return InvalidArgumentError;
+}(MenuError));
+exports.InvalidArgumentError = InvalidArgumentError;
+var ERRORS = {
+    ERR_UNKNOWN_HANDLE: UnknownHandleError,
+    ERR_MENU_BUSY: MenuBusyError,
+    ERR_INVALID_TEMPLATE: InvalidTemplateError,
+    ERR_INVALID_CONFIG: InvalidConfigError,
+    ERR_BACKEND_FAILURE: BackendFailureError,
+    ERR_INVALID_ARGUMENT: InvalidArgumentError,
+};
+var toMenuError = function (e) {
+    if (e instanceof Error && "code" in e && typeof e.code == "string" && ERRORS[e.code]) {
+        var error = new ERRORS[e.code](e.message);
+        error.stack = e.stack;
+        if (Array.isArray(e.diagnostics)) {
+            error.diagnostics = e.diagnostics;
+        }
+        return error;
+    }
+    return e;
+};
+var call = function (fn) {
+    try {
+        return fn();
+    }
+    catch (e) {
+        throw toMenuError(e);
+    }
+};
 var UUID = "MenuItem";
-var getDefaultConfig = function () {
-    return PopupMenu.getDefaultConfig();
+var ABORTED = "aborted";
+var getDefaultConfig = function (options) {
+    return call(function () { return PopupMenu.getDefaultConfig(options); });
 };
 exports.getDefaultConfig = getDefaultConfig;
+var validateTemplate = function (template) {
+    return call(function () { return PopupMenu.validateTemplate(template); });
+};
+exports.validateTemplate = validateTemplate;
+/**
+ * Reports every out-of-range or unknown value of the config, as strict mode would.
+ */
+var validateConfig = function (config) {
+    return call(function () { return PopupMenu.validateConfig(config); });
+};
+exports.validateConfig = validateConfig;
+var getSystemTheme = function () {
+    return call(function () { return PopupMenu.getSystemTheme(); });
+};
+exports.getSystemTheme = getSystemTheme;
+/**
+ * Calls back whenever the desktop switches between dark and light. Menus built with the "system" theme follow it on their own.
+ * Returns a function that removes the callback.
+ */
+var onSystemThemeChanged = function (callback) {
+    var id = call(function () { return PopupMenu.onSystemThemeChanged(callback); });
+    return function () {
+        PopupMenu.offSystemThemeChanged(id);
+    };
+};
+exports.onSystemThemeChanged = onSystemThemeChanged;
+/**
+ * Registers a preset that can be passed wherever a theme is accepted. The config is merged over the default config.
+ * Registering a name again replaces the preset for menus built afterwards.
+ */
+var registerTheme = function (name, config, options) {
+    if (options === void 0) { options = {}; }
+    call(function () { return PopupMenu.registerTheme(name, config, options); });
+};
+exports.registerTheme = registerTheme;
+/**
+ * Returns the built-in themes and presets followed by the registered presets.
+ */
+var listThemes = function () {
+    return call(function () { return PopupMenu.listThemes(); });
+};
+exports.listThemes = listThemes;
 var Menu = /** @class */ (function () {
     function Menu() {
         this.menuWindowHandle = 0;
+        this.handle = 0;
         this.type = "";
         this.callbacks = {};
         this.uuid = 0;
@@ -88,20 +259,59 @@ var Menu = /** @class */ (function () {
         if (!this.menuWindowHandle)
             throw new Error("Menu does not exist");
     };
+    /**
+     * Returns the opaque id of the menu.
+     */
     Menu.prototype.getWindowHandle = function () {
         return this.menuWindowHandle;
     };
+    /**
+     * Returns the GtkMenu pointer on Linux or the HWND of the menu window on Windows.
+     */
+    Menu.prototype.getNativeHandle = function () {
+        var _this = this;
+        this.ready();
+        return call(function () { return PopupMenu.getNativeHandle(_this.handle); });
+    };
+    Menu.prototype.build = function (options) {
+        var template = this.toEffectiveTemplates(options.template);
+        this.setHandle(call(function () { return PopupMenu.buildMenu(__assign(__assign({}, options), { template: template, boxed: true })); }));
+    };
     Menu.prototype.buildFromTemplate = function (menuWindowHandle, template) {
-        var effectiveTemplate = this.toEffectiveTemplates(template);
-        this.menuWindowHandle = PopupMenu.buildFromTemplate(menuWindowHandle, effectiveTemplate);
+        this.build({ parent: menuWindowHandle, template: template });
     };
     Menu.prototype.buildFromTemplateWithTheme = function (menuWindowHandle, template, theme) {
-        var effectiveTemplate = this.toEffectiveTemplates(template);
-        this.menuWindowHandle = PopupMenu.buildFromTemplateWithTheme(menuWindowHandle, effectiveTemplate, theme);
+        this.build({ parent: menuWindowHandle, template: template, theme: theme });
+    };
+    Menu.prototype.buildFromTemplateWithConfig = function (menuWindowHandle, template, config, options) {
+        if (options === void 0) { options = {}; }
+        this.build({ parent: menuWindowHandle, template: template, config: config, strict: options.strict });
     };
-    Menu.prototype.buildFromTemplateWithConfig = function (menuWindowHandle, template, config) {
-        var effectiveTemplate = this.toEffectiveTemplates(template);
-        this.menuWindowHandle = PopupMenu.buildFromTemplateWithConfig(menuWindowHandle, effectiveTemplate, config);
+    /**
+     * Builds the menu from a JSON or TOML file holding a template and an optional config.
+     * A watched menu keeps its handle across rebuilds. Like other menus, it is released by destroy, or once this Menu
+     * is garbage collected, which also stops watching the file.
+     */
+    Menu.prototype.buildFromFile = function (menuWindowHandle, path, options) {
+        if (options === void 0) { options = {}; }
+        var handlers = options.handlers, onReload = options.onReload, fileOptions = __rest(options, ["handlers", "onReload"]);
+        var listener = onReload
+            ? function (event) {
+                if (event.type == "reloaded") {
+                    onReload(event);
+                }
+                else {
+                    var error = toMenuError(event.error);
+                    onReload({ type: "reloadFailed", error: error, diagnostics: error.diagnostics || [] });
+                }
+            }
+            : undefined;
+        this.setHandle(call(function () { return PopupMenu.buildFromFile(menuWindowHandle, path, __assign(__assign({}, fileOptions), { onReload: listener, boxed: true })); }));
+        this.callbacks = __assign({}, handlers);
+    };
+    Menu.prototype.setHandle = function (handle) {
+        this.handle = handle;
+        this.menuWindowHandle = call(function () { return PopupMenu.getHandle(handle); });
     };
     Menu.prototype.toEffectiveTemplates = function (items) {
         var _this = this;
@@ -138,59 +348,268 @@ var Menu = /** @class */ (function () {
         return type;
     };
     Menu.prototype.toMenuItem = function (item) {
+        var _this = this;
         var submenu = new Menu();
         if (item.submenu && Object.keys(item.submenu).length) {
-            submenu.menuWindowHandle = item.submenu.menuWindowHandle;
+            submenu.setHandle(item.submenu.menuWindowHandle);
             submenu.type = item.submenu.type;
+            submenu.callbacks = this.callbacks;
+        }
+        var menuItem = __assign(__assign({}, item), { click: this.callbacks[item.id], submenu: submenu });
+        if (item.items) {
+            menuItem.items = item.items.map(function (child) { return _this.toMenuItem(child); });
         }
-        return __assign(__assign({}, item), { click: this.callbacks[item.id], submenu: submenu });
+        return menuItem;
     };
-    Menu.prototype.popup = function (x, y) {
-        return __awaiter(this, void 0, void 0, function () {
-            var result;
-            return __generator(this, function (_a) {
-                switch (_a.label) {
+    Menu.prototype.popup = function (x_1, y_1) {
+        return __awaiter(this, arguments, void 0, function (x, y, options) {
+            var signal, onAbort, _a, closeReason, path, modifiers, input, cancelled, reason, item, result, callback;
+            var _this = this;
+            if (options === void 0) { options = {}; }
+            return __generator(this, function (_b) {
+                switch (_b.label) {
                     case 0:
                         this.ready();
-                        return [4 /*yield*/, PopupMenu.popup(this.menuWindowHandle, x, y)];
+                        signal = options.signal;
+                        if (signal && signal.aborted) {
+                            return [2 /*return*/, { closeReason: "cancelled", path: [], modifiers: { shift: false, ctrl: false, alt: false, meta: false }, input: null, cancelled: true, reason: signal.reason }];
+                        }
+                        onAbort = function () { return _this.close(ABORTED); };
+                        if (signal) {
+                            signal.addEventListener("abort", onAbort);
+                        }
+                        _b.label = 1;
                     case 1:
-                        result = _a.sent();
-                        if (Object.keys(result).length) {
-                            this.callbacks[result.id](result);
+                        _b.trys.push([1, , 3, 4]);
+                        return [4 /*yield*/, PopupMenu.popup(this.handle, x, y).catch(function (e) {
+                                throw toMenuError(e);
+                            })];
+                    case 2:
+                        _a = _b.sent(), closeReason = _a.closeReason, path = _a.path, modifiers = _a.modifiers, input = _a.input, cancelled = _a.cancelled, reason = _a.reason, item = __rest(_a, ["closeReason", "path", "modifiers", "input", "cancelled", "reason"]);
+                        result = { closeReason: closeReason, path: path, modifiers: modifiers, input: input };
+                        if (cancelled) {
+                            result.cancelled = cancelled;
+                            result.reason = signal && signal.aborted && reason == ABORTED ? signal.reason : reason;
+                        }
+                        if (closeReason == "selected") {
+                            result.item = this.toMenuItem(item);
+                            callback = this.callbacks[result.item.id];
+                            if (callback) {
+                                callback(result.item, result);
+                            }
                         }
-                        return [2 /*return*/];
+                        return [2 /*return*/, result];
+                    case 3:
+                        if (signal) {
+                            signal.removeEventListener("abort", onAbort);
+                        }
+                        return [7 /*endfinally*/];
+                    case 4: return [2 /*return*/];
                 }
             });
         });
     };
-    Menu.prototype.items = function () {
+    Menu.prototype.close = function (reason) {
+        var _this = this;
+        if (reason === void 0) { reason = "closed"; }
+        this.ready();
+        return call(function () { return PopupMenu.close(_this.handle, reason); });
+    };
+    Menu.prototype.items = function (options) {
+        var _this = this;
+        if (options === void 0) { options = {}; }
+        this.ready();
+        return call(function () { return PopupMenu.items(_this.handle, options); }).map(function (item) { return _this.toMenuItem(item); });
+    };
+    /**
+     * Returns the menu as a nested template that buildFromTemplate builds into the same menu.
+     * Items keep the click handlers the menu holds for them.
+     */
+    Menu.prototype.toTemplate = function () {
+        var _this = this;
+        this.ready();
+        return this.attachCallbacks(call(function () { return PopupMenu.toTemplate(_this.handle); }));
+    };
+    Menu.prototype.attachCallbacks = function (template) {
+        var _this = this;
+        template.forEach(function (item) {
+            if (item.id && _this.callbacks[item.id]) {
+                item.click = _this.callbacks[item.id];
+            }
+            if (item.submenu) {
+                _this.attachCallbacks(item.submenu);
+            }
+        });
+        return template;
+    };
+    /**
+     * Changes the menu to match the template, adding, removing, moving and updating only the items that differ.
+     * Items are matched by id, even across submenus, and a kept submenu item keeps the handle of its submenu.
+     * Items without an id are given a new one and built again, so pass ids, or edit the result of toTemplate.
+     * Separators are matched by position.
+     * The changes are checked before any is applied, so the menu is left unchanged if one of them fails.
+     */
+    Menu.prototype.setTemplate = function (template) {
         var _this = this;
         this.ready();
-        return PopupMenu.items(this.menuWindowHandle).map(function (item) { return _this.toMenuItem(item); });
+        var items = this.toEffectiveTemplates(template);
+        call(function () { return PopupMenu.setTemplate(_this.handle, items); });
     };
     Menu.prototype.remove = function (item) {
+        var _this = this;
         this.ready();
-        PopupMenu.remove(this.menuWindowHandle, item);
+        call(function () { return PopupMenu.remove(_this.handle, item); });
     };
     Menu.prototype.removeAt = function (index) {
+        var _this = this;
         this.ready();
-        PopupMenu.removeAt(this.menuWindowHandle, index);
+        call(function () { return PopupMenu.removeAt(_this.handle, index); });
     };
+    /**
+     * Adds an item at the end. A submenu item is built along with its submenu template, and the submenu is returned.
+     */
     Menu.prototype.append = function (item) {
+        var _this = this;
         this.ready();
-        PopupMenu.append(this.menuWindowHandle, this.toEffectiveTemplate(item));
+        return this.toSubmenu(call(function () { return PopupMenu.append(_this.handle, _this.toRuntimeItem(item)); }));
     };
     Menu.prototype.insert = function (index, item) {
+        var _this = this;
+        this.ready();
+        return this.toSubmenu(call(function () { return PopupMenu.insert(_this.handle, index, _this.toRuntimeItem(item)); }));
+    };
+    /**
+     * Removes the item with the id, which may be in a submenu.
+     */
+    Menu.prototype.removeById = function (id) {
+        var _this = this;
+        this.ready();
+        call(function () { return PopupMenu.removeById(_this.handle, id); });
+    };
+    /**
+     * Inserts an item before the item with the id, in the menu or submenu holding it. Returns a submenu as append does.
+     */
+    Menu.prototype.insertBefore = function (id, item) {
+        var _this = this;
         this.ready();
-        PopupMenu.insert(this.menuWindowHandle, index, this.toEffectiveTemplate(item));
+        return this.toSubmenu(call(function () { return PopupMenu.insertBefore(_this.handle, id, _this.toRuntimeItem(item)); }));
     };
+    /**
+     * Inserts an item after the item with the id, in the menu or submenu holding it. Returns a submenu as append does.
+     */
+    Menu.prototype.insertAfter = function (id, item) {
+        var _this = this;
+        this.ready();
+        return this.toSubmenu(call(function () { return PopupMenu.insertAfter(_this.handle, id, _this.toRuntimeItem(item)); }));
+    };
+    /**
+     * Moves the item with the id into the submenu of the item with targetParentId, or into this menu if it is null,
+     * so that it ends up at the index there. A moved submenu keeps its handle.
+     */
+    Menu.prototype.moveItem = function (id, targetParentId, index) {
+        var _this = this;
+        this.ready();
+        call(function () { return PopupMenu.moveItem(_this.handle, id, targetParentId, index); });
+    };
+    Menu.prototype.toRuntimeItem = function (item) {
+        var newItem = this.toEffectiveTemplate(item);
+        if (Array.isArray(newItem.submenu)) {
+            this.toEffectiveTemplates(newItem.submenu);
+        }
+        return newItem;
+    };
+    Menu.prototype.toSubmenu = function (handle) {
+        if (handle === undefined) {
+            return;
+        }
+        var submenu = new Menu();
+        submenu.setHandle(handle);
+        submenu.type = "submenu";
+        // Clicks are reported by the main menu
+        submenu.callbacks = this.callbacks;
+        return submenu;
+    };
+    /**
+     * Switches between dark, light and system, or applies a preset in full.
+     */
     Menu.prototype.setTheme = function (theme) {
+        var _this = this;
         this.ready();
-        PopupMenu.setTheme(this.menuWindowHandle, theme);
+        call(function () { return PopupMenu.setTheme(_this.handle, theme); });
+    };
+    /**
+     * Applies a partial config to the menu and its submenus. Omitted fields keep their current values.
+     */
+    Menu.prototype.setConfig = function (config, options) {
+        var _this = this;
+        if (options === void 0) { options = {}; }
+        this.ready();
+        call(function () { return PopupMenu.setConfig(_this.handle, config, options); });
+    };
+    Menu.prototype.getConfig = function (options) {
+        var _this = this;
+        this.ready();
+        return call(function () { return PopupMenu.getConfig(_this.handle, options); });
+    };
+    /**
+     * Changes the given fields of the item with the id, which may be in a submenu, and returns the updated item.
+     * Checking a radio item unchecks the others in its group.
+     */
+    Menu.prototype.updateItem = function (id, patch) {
+        var _this = this;
+        this.ready();
+        var click = patch.click, fields = __rest(patch, ["click"]);
+        var item = this.toMenuItem(call(function () { return PopupMenu.updateItem(_this.handle, id, fields); }));
+        if (click) {
+            this.callbacks[id] = click;
+            item.click = click;
+        }
+        return item;
+    };
+    /**
+     * Applies the operations in order with the menu locked once, and returns what each method would have returned.
+     * Every operation is checked before any is applied, so the menu is left unchanged if one of them is invalid.
+     * The native menu is then changed in one pass, and an added submenu is laid out once with all of its items.
+     */
+    Menu.prototype.batch = function (ops) {
+        var _this = this;
+        this.ready();
+        var nativeOps = ops.map(function (op) {
+            if (op.op === "updateItem") {
+                var _a = op.patch, click = _a.click, patch = __rest(_a, ["click"]);
+                return __assign(__assign({}, op), { patch: patch });
+            }
+            if ("item" in op) {
+                return __assign(__assign({}, op), { item: _this.toRuntimeItem(op.item) });
+            }
+            return op;
+        });
+        var results = call(function () { return PopupMenu.batch(_this.handle, nativeOps); });
+        return results.map(function (result, index) {
+            var op = ops[index];
+            if (op.op !== "updateItem") {
+                return _this.toSubmenu(result);
+            }
+            var item = _this.toMenuItem(result);
+            if (op.patch.click) {
+                _this.callbacks[op.id] = op.patch.click;
+                item.click = op.patch.click;
+            }
+            return item;
+        });
+    };
+    Menu.prototype.destroy = function () {
+        var _this = this;
+        this.ready();
+        call(function () { return PopupMenu.destroy(_this.handle); });
+        this.handle = 0;
+        this.menuWindowHandle = 0;
+        this.callbacks = {};
     };
     Menu.prototype.getMenuItemById = function (id) {
+        var _this = this;
         this.ready();
-        var item = PopupMenu.getMenuItemById(this.menuWindowHandle, id);
+        var item = call(function () { return PopupMenu.getMenuItemById(_this.handle, id); });
         if (item) {
             return this.toMenuItem(item);
         }
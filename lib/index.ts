@@ -24,26 +24,36 @@ export type MenuItem = {
     submenu?: Menu;
     name?: string;
     click?: Function;
+    /** The items of the submenu, listed by items() down to the requested depth. */
+    items?: MenuItem[];
 };
 
 export type Theme = "dark" | "light" | "system";
+/** A theme or the name of a preset from listThemes(). */
+export type ThemeName = Theme | "high-contrast" | (string & {});
 export type MenuSize = {
     borderSize: number;
-    verticalMargin: number;
-    horizontalMargin: number;
+    verticalPadding: number;
+    horizontalPadding: number;
     itemVerticalPadding: number;
     itemHorizontalPadding: number;
-    fontSize?: number;
-    fontWeight?: number;
+    submenuOffset: number;
 };
 
+/**
+ * A CSS color string such as "#RGB", "#RRGGBB", "#RRGGBBAA", "rgb()", "rgba()" or a named color,
+ * or a number in the native 0xRRGGBB / 0xRRGGBBAA layout.
+ */
+export type Color = string | number;
+
 export type ColorScheme = {
-    color: number;
-    border: number;
-    accelerator: number;
-    disabled: number;
-    backgroundColor: number;
-    hoverBackgroundColor: number;
+    color: Color;
+    border: Color;
+    accelerator: Color;
+    separator: Color;
+    disabled: Color;
+    backgroundColor: Color;
+    hoverBackgroundColor: Color;
 };
 
 export type ThemeColor = {
@@ -70,16 +80,261 @@ export type Config = {
     font: MenuFont;
 };
 
+/**
+ * Omitted sections and fields fall back to the values from getDefaultConfig().
+ */
+export type PartialConfig = {
+    theme?: Theme;
+    size?: Partial<MenuSize>;
+    color?: {
+        dark?: Partial<ColorScheme>;
+        light?: Partial<ColorScheme>;
+    };
+    corner?: Corner;
+    font?: Partial<MenuFont>;
+};
+
+export type ColorFormatOptions = {
+    /** "hex" returns colors as "#rrggbb" or "#rrggbbaa" strings. Defaults to "number". */
+    colorFormat?: "number" | "hex";
+};
+
+export type ItemPatch = Pick<MenuItemConstructorOptions, "label" | "enabled" | "checked" | "accelerator" | "name" | "click">;
+
+export type ItemsOptions = {
+    /** How many levels of submenus to list under items. Every level is listed by default, 0 lists none. */
+    depth?: number;
+};
+
+/**
+ * A change applied by batch. Each one does what the method of the same name does.
+ */
+export type MenuOperation =
+    | { op: "append"; item: MenuItem | MenuItemConstructorOptions }
+    | { op: "insert"; index: number; item: MenuItem | MenuItemConstructorOptions }
+    | { op: "insertBefore" | "insertAfter"; id: string; item: MenuItem | MenuItemConstructorOptions }
+    | { op: "removeAt"; index: number }
+    | { op: "removeById"; id: string }
+    | { op: "updateItem"; id: string; patch: ItemPatch }
+    | { op: "moveItem"; id: string; targetParentId: string | null; index: number };
+
 export type MenuType = "main" | "submenu";
 
+export type BuildOptions = {
+    /** Window handle as a number or the Buffer returned by getNativeWindowHandle(). */
+    parent: number | Buffer;
+    template: MenuItemConstructorOptions[];
+    /** A plain theme takes precedence over config.theme. The config is merged over a preset. */
+    theme?: ThemeName;
+    config?: PartialConfig;
+    /** Rejects wrong types, out-of-range numbers and unknown names in the config instead of falling back. */
+    strict?: boolean;
+};
+
+export type FileOptions = {
+    /** Told from the extension of the file if omitted. */
+    format?: "json" | "toml";
+    /** See BuildOptions.theme. */
+    theme?: ThemeName;
+    /** Merged over the config embedded in the file. */
+    config?: PartialConfig;
+    strict?: boolean;
+    /** Click handlers by item id, since a file cannot hold them. */
+    handlers?: { [id: string]: Function };
+    /** Rebuilds the menu in place whenever the file changes. The file is polled, so this is meant for development. */
+    watch?: boolean;
+    /** Called after each rebuild of a watched menu. A failed rebuild leaves the menu as it was. */
+    onReload?: (event: ReloadEvent) => void;
+};
+
+export type ReloadEvent = { type: "reloaded" } | { type: "reloadFailed"; error: MenuError; diagnostics: Diagnostic[] };
+
+export type ConfigOptions = {
+    /** See BuildOptions.strict. */
+    strict?: boolean;
+};
+
+export type AbortSignalLike = {
+    readonly aborted: boolean;
+    readonly reason?: any;
+    addEventListener(type: "abort", listener: () => void): void;
+    removeEventListener(type: "abort", listener: () => void): void;
+};
+
+export type PopupOptions = {
+    signal?: AbortSignalLike;
+};
+
+export type CloseReason = "selected" | "escape" | "outside" | "cancelled";
+
+export type InputSource = "mouse" | "keyboard";
+
+export type Modifiers = {
+    shift: boolean;
+    ctrl: boolean;
+    alt: boolean;
+    meta: boolean;
+};
+
+export type PopupResult = {
+    closeReason: CloseReason;
+    /** The chosen item, if closeReason is "selected". */
+    item?: MenuItem;
+    /** Ids from the root menu down to the chosen item. */
+    path: string[];
+    /** Modifier keys held when the menu closed. */
+    modifiers: Modifiers;
+    input: InputSource | null;
+    cancelled?: true;
+    reason?: any;
+};
+
+export type MenuErrorCode = "ERR_UNKNOWN_HANDLE" | "ERR_MENU_BUSY" | "ERR_INVALID_TEMPLATE" | "ERR_INVALID_CONFIG" | "ERR_BACKEND_FAILURE" | "ERR_INVALID_ARGUMENT";
+
+export type Diagnostic = {
+    path: string;
+    message: string;
+    /** Set for problems found in a file by buildFromFile. */
+    line?: number;
+    column?: number;
+};
+
+export class MenuError extends Error {
+    readonly code: MenuErrorCode;
+    diagnostics: Diagnostic[] = [];
+
+    constructor(message: string, code: MenuErrorCode) {
+        super(message);
+        Object.setPrototypeOf(this, new.target.prototype);
+        this.name = "MenuError";
+        this.code = code;
+    }
+}
+
+export class UnknownHandleError extends MenuError {
+    constructor(message: string) {
+        super(message, "ERR_UNKNOWN_HANDLE");
+        this.name = "UnknownHandleError";
+    }
+}
+
+export class MenuBusyError extends MenuError {
+    constructor(message: string) {
+        super(message, "ERR_MENU_BUSY");
+        this.name = "MenuBusyError";
+    }
+}
+
+export class InvalidTemplateError extends MenuError {
+    constructor(message: string) {
+        super(message, "ERR_INVALID_TEMPLATE");
+        this.name = "InvalidTemplateError";
+    }
+}
+
+export class InvalidConfigError extends MenuError {
+    constructor(message: string) {
+        super(message, "ERR_INVALID_CONFIG");
+        this.name = "InvalidConfigError";
+    }
+}
+
+export class BackendFailureError extends MenuError {
+    constructor(message: string) {
+        super(message, "ERR_BACKEND_FAILURE");
+        this.name = "BackendFailureError";
+    }
+}
+
+export class InvalidArgumentError extends MenuError {
+    constructor(message: string) {
+        super(message, "ERR_INVALID_ARGUMENT");
+        this.name = "InvalidArgumentError";
+    }
+}
+
+const ERRORS: { [code: string]: new (message: string) => MenuError } = {
+    ERR_UNKNOWN_HANDLE: UnknownHandleError,
+    ERR_MENU_BUSY: MenuBusyError,
+    ERR_INVALID_TEMPLATE: InvalidTemplateError,
+    ERR_INVALID_CONFIG: InvalidConfigError,
+    ERR_BACKEND_FAILURE: BackendFailureError,
+    ERR_INVALID_ARGUMENT: InvalidArgumentError,
+};
+
+const toMenuError = (e: any) => {
+    if (e instanceof Error && "code" in e && typeof e.code == "string" && ERRORS[e.code]) {
+        const error = new ERRORS[e.code](e.message);
+        error.stack = e.stack;
+        if (Array.isArray(e.diagnostics)) {
+            error.diagnostics = e.diagnostics;
+        }
+        return error;
+    }
+    return e;
+};
+
+const call = <T>(fn: () => T): T => {
+    try {
+        return fn();
+    } catch (e) {
+        throw toMenuError(e);
+    }
+};
+
 const UUID = "MenuItem";
+const ABORTED = "aborted";
+
+export const getDefaultConfig = (options?: ColorFormatOptions): Config => {
+    return call(() => PopupMenu.getDefaultConfig(options));
+};
+
+export const validateTemplate = (template: MenuItemConstructorOptions[]): Diagnostic[] => {
+    return call(() => PopupMenu.validateTemplate(template));
+};
+
+/**
+ * Reports every out-of-range or unknown value of the config, as strict mode would.
+ */
+export const validateConfig = (config: PartialConfig): Diagnostic[] => {
+    return call(() => PopupMenu.validateConfig(config));
+};
+
+export type SystemTheme = "dark" | "light";
+
+export const getSystemTheme = (): SystemTheme => {
+    return call(() => PopupMenu.getSystemTheme());
+};
+
+/**
+ * Calls back whenever the desktop switches between dark and light. Menus built with the "system" theme follow it on their own.
+ * Returns a function that removes the callback.
+ */
+export const onSystemThemeChanged = (callback: (theme: SystemTheme) => void): (() => void) => {
+    const id = call(() => PopupMenu.onSystemThemeChanged(callback));
+    return () => {
+        PopupMenu.offSystemThemeChanged(id);
+    };
+};
+
+/**
+ * Registers a preset that can be passed wherever a theme is accepted. The config is merged over the default config.
+ * Registering a name again replaces the preset for menus built afterwards.
+ */
+export const registerTheme = (name: string, config: PartialConfig, options: ConfigOptions = {}) => {
+    call(() => PopupMenu.registerTheme(name, config, options));
+};
 
-export const getDefaultConfig = () => {
-    return PopupMenu.getDefaultConfig();
+/**
+ * Returns the built-in themes and presets followed by the registered presets.
+ */
+export const listThemes = (): string[] => {
+    return call(() => PopupMenu.listThemes());
 };
 
 export class Menu {
     private menuWindowHandle = 0;
+    private handle: PopupMenu.MenuHandle = 0;
     type = "";
     private callbacks: { [key: string]: Function } = {};
     private uuid = 0;
@@ -88,23 +343,62 @@ export class Menu {
         if (!this.menuWindowHandle) throw new Error("Menu does not exist");
     }
 
+    /**
+     * Returns the opaque id of the menu.
+     */
     getWindowHandle(): number {
         return this.menuWindowHandle;
     }
 
-    buildFromTemplate(menuWindowHandle: number, template: MenuItemConstructorOptions[]) {
-        const effectiveTemplate = this.toEffectiveTemplates(template);
-        this.menuWindowHandle = PopupMenu.buildFromTemplate(menuWindowHandle, effectiveTemplate);
+    /**
+     * Returns the GtkMenu pointer on Linux or the HWND of the menu window on Windows.
+     */
+    getNativeHandle(): bigint {
+        this.ready();
+        return call(() => PopupMenu.getNativeHandle(this.handle));
+    }
+
+    build(options: BuildOptions) {
+        const template = this.toEffectiveTemplates(options.template);
+        this.setHandle(call(() => PopupMenu.buildMenu({ ...options, template, boxed: true })));
+    }
+
+    buildFromTemplate(menuWindowHandle: number | Buffer, template: MenuItemConstructorOptions[]) {
+        this.build({ parent: menuWindowHandle, template });
     }
 
-    buildFromTemplateWithTheme(menuWindowHandle: number, template: MenuItemConstructorOptions[], theme: Theme) {
-        const effectiveTemplate = this.toEffectiveTemplates(template);
-        this.menuWindowHandle = PopupMenu.buildFromTemplateWithTheme(menuWindowHandle, effectiveTemplate, theme);
+    buildFromTemplateWithTheme(menuWindowHandle: number | Buffer, template: MenuItemConstructorOptions[], theme: ThemeName) {
+        this.build({ parent: menuWindowHandle, template, theme });
     }
 
-    buildFromTemplateWithConfig(menuWindowHandle: number, template: MenuItemConstructorOptions[], config: Config) {
-        const effectiveTemplate = this.toEffectiveTemplates(template);
-        this.menuWindowHandle = PopupMenu.buildFromTemplateWithConfig(menuWindowHandle, effectiveTemplate, config);
+    buildFromTemplateWithConfig(menuWindowHandle: number | Buffer, template: MenuItemConstructorOptions[], config: PartialConfig, options: ConfigOptions = {}) {
+        this.build({ parent: menuWindowHandle, template, config, strict: options.strict });
+    }
+
+    /**
+     * Builds the menu from a JSON or TOML file holding a template and an optional config.
+     * A watched menu keeps its handle across rebuilds. Like other menus, it is released by destroy, or once this Menu
+     * is garbage collected, which also stops watching the file.
+     */
+    buildFromFile(menuWindowHandle: number | Buffer, path: string, options: FileOptions = {}) {
+        const { handlers, onReload, ...fileOptions } = options;
+        const listener = onReload
+            ? (event: PopupMenu.NativeReloadEvent) => {
+                  if (event.type == "reloaded") {
+                      onReload(event);
+                  } else {
+                      const error = toMenuError(event.error);
+                      onReload({ type: "reloadFailed", error, diagnostics: error.diagnostics || [] });
+                  }
+              }
+            : undefined;
+        this.setHandle(call(() => PopupMenu.buildFromFile(menuWindowHandle, path, { ...fileOptions, onReload: listener, boxed: true })));
+        this.callbacks = { ...handlers };
+    }
+
+    private setHandle(handle: PopupMenu.MenuHandle) {
+        this.handle = handle;
+        this.menuWindowHandle = call(() => PopupMenu.getHandle(handle));
     }
 
     private toEffectiveTemplates(items: MenuItemConstructorOptions[]): MenuItemConstructorOptions[] {
@@ -151,57 +445,256 @@ export class Menu {
     private toMenuItem(item: PopupMenu.PopupMenuItem): MenuItem {
         const submenu = new Menu();
         if (item.submenu && Object.keys(item.submenu).length) {
-            submenu.menuWindowHandle = item.submenu.menuWindowHandle;
+            submenu.setHandle(item.submenu.menuWindowHandle);
             submenu.type = item.submenu.type;
+            submenu.callbacks = this.callbacks;
         }
-        return {
+        const menuItem: MenuItem = {
             ...item,
             click: this.callbacks[item.id],
             submenu,
         };
+        if (item.items) {
+            menuItem.items = item.items.map((child) => this.toMenuItem(child));
+        }
+        return menuItem;
     }
 
-    async popup(x: number, y: number) {
+    async popup(x: number, y: number, options: PopupOptions = {}): Promise<PopupResult> {
         this.ready();
-        const result = await PopupMenu.popup(this.menuWindowHandle, x, y);
-        if (Object.keys(result).length) {
-            this.callbacks[result.id](result);
+        const signal = options.signal;
+        if (signal && signal.aborted) {
+            return { closeReason: "cancelled", path: [], modifiers: { shift: false, ctrl: false, alt: false, meta: false }, input: null, cancelled: true, reason: signal.reason };
+        }
+
+        const onAbort = () => this.close(ABORTED);
+        if (signal) {
+            signal.addEventListener("abort", onAbort);
+        }
+
+        try {
+            const { closeReason, path, modifiers, input, cancelled, reason, ...item } = await PopupMenu.popup(this.handle, x, y).catch((e) => {
+                throw toMenuError(e);
+            });
+            const result: PopupResult = { closeReason, path, modifiers, input };
+            if (cancelled) {
+                result.cancelled = cancelled;
+                result.reason = signal && signal.aborted && reason == ABORTED ? signal.reason : reason;
+            }
+            if (closeReason == "selected") {
+                result.item = this.toMenuItem(item as PopupMenu.PopupMenuItem);
+                const callback = this.callbacks[result.item.id as string];
+                if (callback) {
+                    callback(result.item, result);
+                }
+            }
+            return result;
+        } finally {
+            if (signal) {
+                signal.removeEventListener("abort", onAbort);
+            }
         }
     }
 
-    items(): MenuItem[] {
+    close(reason = "closed"): boolean {
         this.ready();
-        return PopupMenu.items(this.menuWindowHandle).map((item) => this.toMenuItem(item));
+        return call(() => PopupMenu.close(this.handle, reason));
+    }
+
+    items(options: ItemsOptions = {}): MenuItem[] {
+        this.ready();
+        return call(() => PopupMenu.items(this.handle, options)).map((item) => this.toMenuItem(item));
+    }
+
+    /**
+     * Returns the menu as a nested template that buildFromTemplate builds into the same menu.
+     * Items keep the click handlers the menu holds for them.
+     */
+    toTemplate(): MenuItemConstructorOptions[] {
+        this.ready();
+        return this.attachCallbacks(call(() => PopupMenu.toTemplate(this.handle)));
+    }
+
+    private attachCallbacks(template: MenuItemConstructorOptions[]): MenuItemConstructorOptions[] {
+        template.forEach((item) => {
+            if (item.id && this.callbacks[item.id]) {
+                item.click = this.callbacks[item.id];
+            }
+            if (item.submenu) {
+                this.attachCallbacks(item.submenu as MenuItemConstructorOptions[]);
+            }
+        });
+        return template;
+    }
+
+    /**
+     * Changes the menu to match the template, adding, removing, moving and updating only the items that differ.
+     * Items are matched by id, even across submenus, and a kept submenu item keeps the handle of its submenu.
+     * Items without an id are given a new one and built again, so pass ids, or edit the result of toTemplate.
+     * Separators are matched by position.
+     * The changes are checked before any is applied, so the menu is left unchanged if one of them fails.
+     */
+    setTemplate(template: MenuItemConstructorOptions[]) {
+        this.ready();
+        const items = this.toEffectiveTemplates(template);
+        call(() => PopupMenu.setTemplate(this.handle, items));
     }
 
     remove(item: MenuItem) {
         this.ready();
-        PopupMenu.remove(this.menuWindowHandle, item);
+        call(() => PopupMenu.remove(this.handle, item));
     }
 
     removeAt(index: number) {
         this.ready();
-        PopupMenu.removeAt(this.menuWindowHandle, index);
+        call(() => PopupMenu.removeAt(this.handle, index));
+    }
+
+    /**
+     * Adds an item at the end. A submenu item is built along with its submenu template, and the submenu is returned.
+     */
+    append(item: MenuItem | MenuItemConstructorOptions): Menu | void {
+        this.ready();
+        return this.toSubmenu(call(() => PopupMenu.append(this.handle, this.toRuntimeItem(item))));
+    }
+
+    insert(index: number, item: MenuItem | MenuItemConstructorOptions): Menu | void {
+        this.ready();
+        return this.toSubmenu(call(() => PopupMenu.insert(this.handle, index, this.toRuntimeItem(item))));
     }
 
-    append(item: MenuItem) {
+    /**
+     * Removes the item with the id, which may be in a submenu.
+     */
+    removeById(id: string) {
         this.ready();
-        PopupMenu.append(this.menuWindowHandle, this.toEffectiveTemplate(item) as MenuItem);
+        call(() => PopupMenu.removeById(this.handle, id));
     }
 
-    insert(index: number, item: MenuItem) {
+    /**
+     * Inserts an item before the item with the id, in the menu or submenu holding it. Returns a submenu as append does.
+     */
+    insertBefore(id: string, item: MenuItem | MenuItemConstructorOptions): Menu | void {
         this.ready();
-        PopupMenu.insert(this.menuWindowHandle, index, this.toEffectiveTemplate(item) as MenuItem);
+        return this.toSubmenu(call(() => PopupMenu.insertBefore(this.handle, id, this.toRuntimeItem(item))));
+    }
+
+    /**
+     * Inserts an item after the item with the id, in the menu or submenu holding it. Returns a submenu as append does.
+     */
+    insertAfter(id: string, item: MenuItem | MenuItemConstructorOptions): Menu | void {
+        this.ready();
+        return this.toSubmenu(call(() => PopupMenu.insertAfter(this.handle, id, this.toRuntimeItem(item))));
+    }
+
+    /**
+     * Moves the item with the id into the submenu of the item with targetParentId, or into this menu if it is null,
+     * so that it ends up at the index there. A moved submenu keeps its handle.
+     */
+    moveItem(id: string, targetParentId: string | null, index: number) {
+        this.ready();
+        call(() => PopupMenu.moveItem(this.handle, id, targetParentId, index));
+    }
+
+    private toRuntimeItem(item: MenuItem | MenuItemConstructorOptions): MenuItem | MenuItemConstructorOptions {
+        const newItem = this.toEffectiveTemplate(item);
+        if (Array.isArray(newItem.submenu)) {
+            this.toEffectiveTemplates(newItem.submenu);
+        }
+        return newItem;
+    }
+
+    private toSubmenu(handle: number | void): Menu | void {
+        if (handle === undefined) {
+            return;
+        }
+        const submenu = new Menu();
+        submenu.setHandle(handle);
+        submenu.type = "submenu";
+        // Clicks are reported by the main menu
+        submenu.callbacks = this.callbacks;
+        return submenu;
+    }
+
+    /**
+     * Switches between dark, light and system, or applies a preset in full.
+     */
+    setTheme(theme: ThemeName) {
+        this.ready();
+        call(() => PopupMenu.setTheme(this.handle, theme));
+    }
+
+    /**
+     * Applies a partial config to the menu and its submenus. Omitted fields keep their current values.
+     */
+    setConfig(config: PartialConfig, options: ConfigOptions = {}) {
+        this.ready();
+        call(() => PopupMenu.setConfig(this.handle, config, options));
+    }
+
+    getConfig(options?: ColorFormatOptions): Config {
+        this.ready();
+        return call(() => PopupMenu.getConfig(this.handle, options));
+    }
+
+    /**
+     * Changes the given fields of the item with the id, which may be in a submenu, and returns the updated item.
+     * Checking a radio item unchecks the others in its group.
+     */
+    updateItem(id: string, patch: ItemPatch): MenuItem {
+        this.ready();
+        const { click, ...fields } = patch;
+        const item = this.toMenuItem(call(() => PopupMenu.updateItem(this.handle, id, fields)));
+        if (click) {
+            this.callbacks[id] = click;
+            item.click = click;
+        }
+        return item;
+    }
+
+    /**
+     * Applies the operations in order with the menu locked once, and returns what each method would have returned.
+     * Every operation is checked before any is applied, so the menu is left unchanged if one of them is invalid.
+     * The native menu is then changed in one pass, and an added submenu is laid out once with all of its items.
+     */
+    batch(ops: MenuOperation[]): (Menu | MenuItem | void)[] {
+        this.ready();
+        const nativeOps = ops.map((op) => {
+            if (op.op === "updateItem") {
+                const { click, ...patch } = op.patch;
+                return { ...op, patch };
+            }
+            if ("item" in op) {
+                return { ...op, item: this.toRuntimeItem(op.item) };
+            }
+            return op;
+        });
+        const results = call(() => PopupMenu.batch(this.handle, nativeOps));
+        return results.map((result, index) => {
+            const op = ops[index];
+            if (op.op !== "updateItem") {
+                return this.toSubmenu(result as number | void);
+            }
+            const item = this.toMenuItem(result as PopupMenu.PopupMenuItem);
+            if (op.patch.click) {
+                this.callbacks[op.id] = op.patch.click;
+                item.click = op.patch.click;
+            }
+            return item;
+        });
     }
 
-    setTheme(theme: Theme) {
+    destroy() {
         this.ready();
-        PopupMenu.setTheme(this.menuWindowHandle, theme);
+        call(() => PopupMenu.destroy(this.handle));
+        this.handle = 0;
+        this.menuWindowHandle = 0;
+        this.callbacks = {};
     }
 
     getMenuItemById(id: string): MenuItem | void {
         this.ready();
-        const item = PopupMenu.getMenuItemById(this.menuWindowHandle, id);
+        const item = call(() => PopupMenu.getMenuItemById(this.handle, id));
         if (item) {
             return this.toMenuItem(item);
         }
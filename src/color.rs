@@ -0,0 +1,434 @@
+/// A color parsed from a CSS color string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self {
+            r,
+            g,
+            b,
+            a: 0xFF,
+        }
+    }
+}
+
+/// Parses `#RGB`, `#RGBA`, `#RRGGBB`, `#RRGGBBAA`, `rgb()`, `rgba()` and named colors.
+pub fn parse(value: &str) -> Option<Rgba> {
+    let value = value.trim().to_ascii_lowercase();
+
+    if let Some(hex) = value.strip_prefix('#') {
+        return parse_hex(hex);
+    }
+
+    if let Some(args) = value.strip_prefix("rgba(").or_else(|| value.strip_prefix("rgb(")) {
+        return parse_rgb_function(args.strip_suffix(')')?);
+    }
+
+    NAMED_COLORS.binary_search_by(|(name, _)| name.cmp(&value.as_str())).ok().map(|index| NAMED_COLORS[index].1)
+}
+
+fn parse_hex(hex: &str) -> Option<Rgba> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let digit = |index: usize| u8::from_str_radix(&hex[index..index + 1], 16).ok().map(|v| v * 17);
+    let byte = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+
+    match hex.len() {
+        3 | 4 => Some(Rgba {
+            r: digit(0)?,
+            g: digit(1)?,
+            b: digit(2)?,
+            a: if hex.len() == 4 {
+                digit(3)?
+            } else {
+                0xFF
+            },
+        }),
+        6 | 8 => Some(Rgba {
+            r: byte(0)?,
+            g: byte(2)?,
+            b: byte(4)?,
+            a: if hex.len() == 8 {
+                byte(6)?
+            } else {
+                0xFF
+            },
+        }),
+        _ => None,
+    }
+}
+
+/// Accepts both `rgb(255, 0, 0, 0.5)` and `rgb(255 0 0 / 50%)`.
+fn parse_rgb_function(args: &str) -> Option<Rgba> {
+    let (channels, alpha) = match args.split_once('/') {
+        Some((channels, alpha)) => (channels, Some(alpha.trim())),
+        None => (args, None),
+    };
+
+    let mut parts: Vec<&str> = channels.split(|c: char| c == ',' || c.is_whitespace()).filter(|part| !part.is_empty()).collect();
+    let alpha = match (alpha, parts.len()) {
+        (Some(alpha), 3) => Some(alpha),
+        (None, 4) => parts.pop(),
+        (None, 3) => None,
+        _ => return None,
+    };
+
+    let alpha = match alpha {
+        Some(alpha) => parse_alpha(alpha)?,
+        None => 0xFF,
+    };
+
+    Some(Rgba {
+        r: parse_channel(parts[0])?,
+        g: parse_channel(parts[1])?,
+        b: parse_channel(parts[2])?,
+        a: alpha,
+    })
+}
+
+fn parse_channel(value: &str) -> Option<u8> {
+    let value = match value.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().ok()? / 100.0 * 255.0,
+        None => value.parse::<f64>().ok()?,
+    };
+    to_byte(value)
+}
+
+fn parse_alpha(value: &str) -> Option<u8> {
+    let value = match value.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().ok()? / 100.0,
+        None => value.parse::<f64>().ok()?,
+    };
+    to_byte(value * 255.0)
+}
+
+fn to_byte(value: f64) -> Option<u8> {
+    if value.is_finite() {
+        Some(value.round().clamp(0.0, 255.0) as u8)
+    } else {
+        None
+    }
+}
+
+/// Encodes the color the way both backends decode it: `0xRRGGBB` when opaque, `0xRRGGBBAA` otherwise.
+///
+/// The backends only treat values above `0xFFFFFF` as having alpha, so a translucent color with no red
+/// is given a red of 1 to stay distinguishable from an opaque one.
+pub fn to_native(color: Rgba) -> u32 {
+    let Rgba {
+        r,
+        g,
+        b,
+        a,
+    } = color;
+
+    if a == 0xFF {
+        return u32::from_be_bytes([0, r, g, b]);
+    }
+
+    u32::from_be_bytes([r.max(1), g, b, a])
+}
+
+pub fn from_native(value: u32) -> Rgba {
+    let [high, r, g, b] = value.to_be_bytes();
+    if high == 0 {
+        return Rgba::rgb(r, g, b);
+    }
+
+    Rgba {
+        r: high,
+        g: r,
+        b: g,
+        a: b,
+    }
+}
+
+/// Encodes a native value as a Windows `COLORREF`, `0x00BBGGRR`, which is what DWM takes for the window border.
+/// A `COLORREF` has no alpha, so a translucent color is made opaque.
+#[cfg(any(target_os = "windows", test))]
+pub fn to_colorref(value: u32) -> u32 {
+    let Rgba {
+        r,
+        g,
+        b,
+        ..
+    } = from_native(value);
+    u32::from_le_bytes([r, g, b, 0])
+}
+
+#[cfg(any(target_os = "windows", test))]
+pub fn from_colorref(value: u32) -> u32 {
+    let [r, g, b, _] = value.to_le_bytes();
+    to_native(Rgba::rgb(r, g, b))
+}
+
+/// Formats the color as `#rrggbb`, or `#rrggbbaa` if it is translucent.
+pub fn to_hex(color: Rgba) -> String {
+    if color.a == 0xFF {
+        format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", color.r, color.g, color.b, color.a)
+    }
+}
+
+/// CSS named colors, sorted by name.
+const NAMED_COLORS: [(&str, Rgba); 149] = [
+    ("aliceblue", Rgba::rgb(240, 248, 255)),
+    ("antiquewhite", Rgba::rgb(250, 235, 215)),
+    ("aqua", Rgba::rgb(0, 255, 255)),
+    ("aquamarine", Rgba::rgb(127, 255, 212)),
+    ("azure", Rgba::rgb(240, 255, 255)),
+    ("beige", Rgba::rgb(245, 245, 220)),
+    ("bisque", Rgba::rgb(255, 228, 196)),
+    ("black", Rgba::rgb(0, 0, 0)),
+    ("blanchedalmond", Rgba::rgb(255, 235, 205)),
+    ("blue", Rgba::rgb(0, 0, 255)),
+    ("blueviolet", Rgba::rgb(138, 43, 226)),
+    ("brown", Rgba::rgb(165, 42, 42)),
+    ("burlywood", Rgba::rgb(222, 184, 135)),
+    ("cadetblue", Rgba::rgb(95, 158, 160)),
+    ("chartreuse", Rgba::rgb(127, 255, 0)),
+    ("chocolate", Rgba::rgb(210, 105, 30)),
+    ("coral", Rgba::rgb(255, 127, 80)),
+    ("cornflowerblue", Rgba::rgb(100, 149, 237)),
+    ("cornsilk", Rgba::rgb(255, 248, 220)),
+    ("crimson", Rgba::rgb(220, 20, 60)),
+    ("cyan", Rgba::rgb(0, 255, 255)),
+    ("darkblue", Rgba::rgb(0, 0, 139)),
+    ("darkcyan", Rgba::rgb(0, 139, 139)),
+    ("darkgoldenrod", Rgba::rgb(184, 134, 11)),
+    ("darkgray", Rgba::rgb(169, 169, 169)),
+    ("darkgreen", Rgba::rgb(0, 100, 0)),
+    ("darkgrey", Rgba::rgb(169, 169, 169)),
+    ("darkkhaki", Rgba::rgb(189, 183, 107)),
+    ("darkmagenta", Rgba::rgb(139, 0, 139)),
+    ("darkolivegreen", Rgba::rgb(85, 107, 47)),
+    ("darkorange", Rgba::rgb(255, 140, 0)),
+    ("darkorchid", Rgba::rgb(153, 50, 204)),
+    ("darkred", Rgba::rgb(139, 0, 0)),
+    ("darksalmon", Rgba::rgb(233, 150, 122)),
+    ("darkseagreen", Rgba::rgb(143, 188, 143)),
+    ("darkslateblue", Rgba::rgb(72, 61, 139)),
+    ("darkslategray", Rgba::rgb(47, 79, 79)),
+    ("darkslategrey", Rgba::rgb(47, 79, 79)),
+    ("darkturquoise", Rgba::rgb(0, 206, 209)),
+    ("darkviolet", Rgba::rgb(148, 0, 211)),
+    ("deeppink", Rgba::rgb(255, 20, 147)),
+    ("deepskyblue", Rgba::rgb(0, 191, 255)),
+    ("dimgray", Rgba::rgb(105, 105, 105)),
+    ("dimgrey", Rgba::rgb(105, 105, 105)),
+    ("dodgerblue", Rgba::rgb(30, 144, 255)),
+    ("firebrick", Rgba::rgb(178, 34, 34)),
+    ("floralwhite", Rgba::rgb(255, 250, 240)),
+    ("forestgreen", Rgba::rgb(34, 139, 34)),
+    ("fuchsia", Rgba::rgb(255, 0, 255)),
+    ("gainsboro", Rgba::rgb(220, 220, 220)),
+    ("ghostwhite", Rgba::rgb(248, 248, 255)),
+    ("gold", Rgba::rgb(255, 215, 0)),
+    ("goldenrod", Rgba::rgb(218, 165, 32)),
+    ("gray", Rgba::rgb(128, 128, 128)),
+    ("green", Rgba::rgb(0, 128, 0)),
+    ("greenyellow", Rgba::rgb(173, 255, 47)),
+    ("grey", Rgba::rgb(128, 128, 128)),
+    ("honeydew", Rgba::rgb(240, 255, 240)),
+    ("hotpink", Rgba::rgb(255, 105, 180)),
+    ("indianred", Rgba::rgb(205, 92, 92)),
+    ("indigo", Rgba::rgb(75, 0, 130)),
+    ("ivory", Rgba::rgb(255, 255, 240)),
+    ("khaki", Rgba::rgb(240, 230, 140)),
+    ("lavender", Rgba::rgb(230, 230, 250)),
+    ("lavenderblush", Rgba::rgb(255, 240, 245)),
+    ("lawngreen", Rgba::rgb(124, 252, 0)),
+    ("lemonchiffon", Rgba::rgb(255, 250, 205)),
+    ("lightblue", Rgba::rgb(173, 216, 230)),
+    ("lightcoral", Rgba::rgb(240, 128, 128)),
+    ("lightcyan", Rgba::rgb(224, 255, 255)),
+    ("lightgoldenrodyellow", Rgba::rgb(250, 250, 210)),
+    ("lightgray", Rgba::rgb(211, 211, 211)),
+    ("lightgreen", Rgba::rgb(144, 238, 144)),
+    ("lightgrey", Rgba::rgb(211, 211, 211)),
+    ("lightpink", Rgba::rgb(255, 182, 193)),
+    ("lightsalmon", Rgba::rgb(255, 160, 122)),
+    ("lightseagreen", Rgba::rgb(32, 178, 170)),
+    ("lightskyblue", Rgba::rgb(135, 206, 250)),
+    ("lightslategray", Rgba::rgb(119, 136, 153)),
+    ("lightslategrey", Rgba::rgb(119, 136, 153)),
+    ("lightsteelblue", Rgba::rgb(176, 196, 222)),
+    ("lightyellow", Rgba::rgb(255, 255, 224)),
+    ("lime", Rgba::rgb(0, 255, 0)),
+    ("limegreen", Rgba::rgb(50, 205, 50)),
+    ("linen", Rgba::rgb(250, 240, 230)),
+    ("magenta", Rgba::rgb(255, 0, 255)),
+    ("maroon", Rgba::rgb(128, 0, 0)),
+    ("mediumaquamarine", Rgba::rgb(102, 205, 170)),
+    ("mediumblue", Rgba::rgb(0, 0, 205)),
+    ("mediumorchid", Rgba::rgb(186, 85, 211)),
+    ("mediumpurple", Rgba::rgb(147, 112, 219)),
+    ("mediumseagreen", Rgba::rgb(60, 179, 113)),
+    ("mediumslateblue", Rgba::rgb(123, 104, 238)),
+    ("mediumspringgreen", Rgba::rgb(0, 250, 154)),
+    ("mediumturquoise", Rgba::rgb(72, 209, 204)),
+    ("mediumvioletred", Rgba::rgb(199, 21, 133)),
+    ("midnightblue", Rgba::rgb(25, 25, 112)),
+    ("mintcream", Rgba::rgb(245, 255, 250)),
+    ("mistyrose", Rgba::rgb(255, 228, 225)),
+    ("moccasin", Rgba::rgb(255, 228, 181)),
+    ("navajowhite", Rgba::rgb(255, 222, 173)),
+    ("navy", Rgba::rgb(0, 0, 128)),
+    ("oldlace", Rgba::rgb(253, 245, 230)),
+    ("olive", Rgba::rgb(128, 128, 0)),
+    ("olivedrab", Rgba::rgb(107, 142, 35)),
+    ("orange", Rgba::rgb(255, 165, 0)),
+    ("orangered", Rgba::rgb(255, 69, 0)),
+    ("orchid", Rgba::rgb(218, 112, 214)),
+    ("palegoldenrod", Rgba::rgb(238, 232, 170)),
+    ("palegreen", Rgba::rgb(152, 251, 152)),
+    ("paleturquoise", Rgba::rgb(175, 238, 238)),
+    ("palevioletred", Rgba::rgb(219, 112, 147)),
+    ("papayawhip", Rgba::rgb(255, 239, 213)),
+    ("peachpuff", Rgba::rgb(255, 218, 185)),
+    ("peru", Rgba::rgb(205, 133, 63)),
+    ("pink", Rgba::rgb(255, 192, 203)),
+    ("plum", Rgba::rgb(221, 160, 221)),
+    ("powderblue", Rgba::rgb(176, 224, 230)),
+    ("purple", Rgba::rgb(128, 0, 128)),
+    ("rebeccapurple", Rgba::rgb(102, 51, 153)),
+    ("red", Rgba::rgb(255, 0, 0)),
+    ("rosybrown", Rgba::rgb(188, 143, 143)),
+    ("royalblue", Rgba::rgb(65, 105, 225)),
+    ("saddlebrown", Rgba::rgb(139, 69, 19)),
+    ("salmon", Rgba::rgb(250, 128, 114)),
+    ("sandybrown", Rgba::rgb(244, 164, 96)),
+    ("seagreen", Rgba::rgb(46, 139, 87)),
+    ("seashell", Rgba::rgb(255, 245, 238)),
+    ("sienna", Rgba::rgb(160, 82, 45)),
+    ("silver", Rgba::rgb(192, 192, 192)),
+    ("skyblue", Rgba::rgb(135, 206, 235)),
+    ("slateblue", Rgba::rgb(106, 90, 205)),
+    ("slategray", Rgba::rgb(112, 128, 144)),
+    ("slategrey", Rgba::rgb(112, 128, 144)),
+    ("snow", Rgba::rgb(255, 250, 250)),
+    ("springgreen", Rgba::rgb(0, 255, 127)),
+    ("steelblue", Rgba::rgb(70, 130, 180)),
+    ("tan", Rgba::rgb(210, 180, 140)),
+    ("teal", Rgba::rgb(0, 128, 128)),
+    ("thistle", Rgba::rgb(216, 191, 216)),
+    ("tomato", Rgba::rgb(255, 99, 71)),
+    (
+        "transparent",
+        Rgba {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        },
+    ),
+    ("turquoise", Rgba::rgb(64, 224, 208)),
+    ("violet", Rgba::rgb(238, 130, 238)),
+    ("wheat", Rgba::rgb(245, 222, 179)),
+    ("white", Rgba::rgb(255, 255, 255)),
+    ("whitesmoke", Rgba::rgb(245, 245, 245)),
+    ("yellow", Rgba::rgb(255, 255, 0)),
+    ("yellowgreen", Rgba::rgb(154, 205, 50)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(r: u8, g: u8, b: u8, a: u8) -> Rgba {
+        Rgba {
+            r,
+            g,
+            b,
+            a,
+        }
+    }
+
+    #[test]
+    fn parses_hex() {
+        assert_eq!(parse("#f80"), Some(Rgba::rgb(0xff, 0x88, 0x00)));
+        assert_eq!(parse("#f808"), Some(rgba(0xff, 0x88, 0x00, 0x88)));
+        assert_eq!(parse("#1A4A99"), Some(Rgba::rgb(0x1a, 0x4a, 0x99)));
+        assert_eq!(parse(" #1a4a9980 "), Some(rgba(0x1a, 0x4a, 0x99, 0x80)));
+        assert_eq!(parse("#12345"), None);
+        assert_eq!(parse("#12g"), None);
+        assert_eq!(parse("#"), None);
+        assert_eq!(parse("#+1+2+3"), None);
+    }
+
+    #[test]
+    fn parses_rgb_functions() {
+        assert_eq!(parse("rgb(255, 0, 128)"), Some(Rgba::rgb(255, 0, 128)));
+        assert_eq!(parse("RGB(255 0 128)"), Some(Rgba::rgb(255, 0, 128)));
+        assert_eq!(parse("rgba(255, 0, 0, 0.5)"), Some(rgba(255, 0, 0, 128)));
+        assert_eq!(parse("rgb(255 0 0 / 25%)"), Some(rgba(255, 0, 0, 64)));
+        assert_eq!(parse("rgb(100%, 50%, 0%)"), Some(Rgba::rgb(255, 128, 0)));
+        // Out of range channels are clamped, as in CSS
+        assert_eq!(parse("rgb(300, -20, 0, 2)"), Some(Rgba::rgb(255, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_malformed_rgb_functions() {
+        assert_eq!(parse("rgb(255, 0)"), None);
+        assert_eq!(parse("rgb(255, 0, 0"), None);
+        assert_eq!(parse("rgb(255, 0, 0, 1 / 1)"), None);
+        assert_eq!(parse("rgb(red, 0, 0)"), None);
+        assert_eq!(parse("rgb(inf, 0, 0)"), None);
+    }
+
+    #[test]
+    fn parses_named_colors() {
+        assert_eq!(parse("rebeccapurple"), Some(Rgba::rgb(102, 51, 153)));
+        assert_eq!(parse("White"), Some(Rgba::rgb(255, 255, 255)));
+        assert_eq!(parse("transparent"), Some(rgba(0, 0, 0, 0)));
+        assert_eq!(parse("blurple"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn named_colors_are_sorted() {
+        assert!(NAMED_COLORS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(NAMED_COLORS.iter().all(|(name, color)| parse(name) == Some(*color)));
+    }
+
+    #[test]
+    fn native_values_keep_alpha_only_when_translucent() {
+        assert_eq!(to_native(Rgba::rgb(0x1a, 0x4a, 0x99)), 0x1a4a99);
+        assert_eq!(to_native(rgba(0x1a, 0x4a, 0x99, 0x80)), 0x1a4a9980);
+        // A translucent color with no red would otherwise read back as opaque
+        assert_eq!(to_native(rgba(0, 0, 0, 0)), 0x01000000);
+    }
+
+    #[test]
+    fn native_values_round_trip() {
+        for color in [Rgba::rgb(0, 0, 0), Rgba::rgb(0xff, 0xee, 0x32), rgba(0x1a, 0x4a, 0x99, 0x80), rgba(0xff, 0, 0, 0)] {
+            assert_eq!(from_native(to_native(color)), color);
+        }
+        assert_eq!(from_native(0xffffff), Rgba::rgb(0xff, 0xff, 0xff));
+    }
+
+    #[test]
+    fn colorrefs_are_blue_green_red() {
+        assert_eq!(to_colorref(to_native(Rgba::rgb(0xff, 0, 0))), 0x0000ff);
+        assert_eq!(to_colorref(to_native(Rgba::rgb(0x1a, 0x4a, 0x99))), 0x994a1a);
+        // DWM rejects a COLORREF with a high byte, so alpha is dropped
+        assert_eq!(to_colorref(to_native(rgba(0x10, 0, 0xff, 0x80))), 0xff0010);
+        assert_eq!(from_colorref(0x994a1a), 0x1a4a99);
+    }
+
+    #[test]
+    fn formats_hex() {
+        assert_eq!(to_hex(Rgba::rgb(0x1a, 0x4a, 0x99)), "#1a4a99");
+        assert_eq!(to_hex(rgba(0x1a, 0x4a, 0x99, 0x80)), "#1a4a9980");
+        assert_eq!(parse(&to_hex(rgba(1, 2, 3, 4))), Some(rgba(1, 2, 3, 4)));
+    }
+}
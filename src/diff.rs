@@ -0,0 +1,474 @@
+use crate::{
+    edit::Step,
+    error::{Diagnostic, MenuError},
+    types::{ElectronMenuItem, ItemPatch},
+};
+use std::collections::HashSet;
+
+/// Reports ids used by more than one item, since items are matched by id.
+pub fn check_ids(template: &[ElectronMenuItem]) -> Result<(), MenuError> {
+    fn visit(items: &[ElectronMenuItem], path: &str, ids: &mut HashSet<String>, diagnostics: &mut Vec<Diagnostic>) {
+        for (index, item) in items.iter().enumerate() {
+            let item_path = format!("{}[{}]", path, index);
+            if is_keyed(item) && !ids.insert(item.id.clone()) {
+                diagnostics.push(Diagnostic::new(&format!("{}.id", item_path), format!("duplicate id \"{}\"", item.id)));
+            }
+            visit(&item.submenu, &format!("{}.submenu", item_path), ids, diagnostics);
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+    visit(template, "template", &mut HashSet::new(), &mut diagnostics);
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(MenuError::InvalidTemplate(diagnostics))
+    }
+}
+
+/// Finds the steps that turn the items into the template. Inserted items come without the items of their submenu,
+/// which are added by later steps.
+///
+/// Items are matched by id wherever they are, so that a kept submenu item keeps its submenu. Separators and
+/// items without an id are matched by position among the items around them. Items that already are in the
+/// right order stay where they are and the others are moved around them. Items left over are removed last,
+/// once the items kept from them have been moved out.
+pub fn diff(items: Vec<ElectronMenuItem>, template: &[ElectronMenuItem]) -> Vec<Step> {
+    let mut model = Model {
+        items: Vec::new(),
+        last_key: ROOT,
+        steps: Vec::new(),
+    };
+    model.items = model.slots_of(items);
+    model.reconcile(ROOT, template);
+    model.remove_unplaced(Vec::new());
+    model.steps
+}
+
+/// The key of the menu being changed.
+const ROOT: usize = 0;
+
+/// An item of the menu as the steps found so far leave it.
+struct Slot {
+    key: usize,
+    /// The fields of the item. Its submenu is kept in items instead.
+    item: ElectronMenuItem,
+    items: Vec<Slot>,
+    /// Whether the item has been matched with the template, which leaves it in place.
+    placed: bool,
+}
+
+struct Model {
+    items: Vec<Slot>,
+    last_key: usize,
+    steps: Vec<Step>,
+}
+
+impl Model {
+    fn slots_of(&mut self, items: Vec<ElectronMenuItem>) -> Vec<Slot> {
+        items
+            .into_iter()
+            .map(|mut item| {
+                let submenu = std::mem::take(&mut item.submenu);
+                Slot {
+                    key: self.next_key(),
+                    item,
+                    items: self.slots_of(submenu),
+                    placed: false,
+                }
+            })
+            .collect()
+    }
+
+    fn next_key(&mut self) -> usize {
+        self.last_key += 1;
+        self.last_key
+    }
+
+    /// Places the items of the template in order at the start of the menu with the key.
+    fn reconcile(&mut self, container: usize, template: &[ElectronMenuItem]) {
+        let stable = self.stable_keys(container, template);
+        let mut last = None;
+
+        for wanted in template {
+            let path = self.path_of(container);
+            let items = self.items_at(&path);
+            let cursor = last.and_then(|last| items.iter().position(|slot| slot.key == last)).map_or(0, |index| index + 1);
+
+            let key = if is_keyed(wanted) {
+                match items.iter().find(|slot| stable.contains(&slot.key) && slot.item.id == wanted.id) {
+                    Some(slot) => slot.key,
+                    None => match self.find_unplaced(&self.items, wanted) {
+                        Some((parent, index)) if parent == path && index == cursor => self.items_at(&path)[index].key,
+                        Some((parent, index)) => self.move_slot(container, parent, index, last),
+                        None => self.insert_slot(&path, cursor, wanted),
+                    },
+                }
+            } else {
+                // Stops at the next item kept in place, so that it is not skipped
+                let limit = items.iter().skip(cursor).position(|slot| stable.contains(&slot.key)).map_or(items.len(), |offset| cursor + offset);
+                match items[cursor..limit].iter().find(|slot| !slot.placed && !is_keyed(&slot.item) && slot.item.itype == wanted.itype) {
+                    Some(slot) => slot.key,
+                    None => self.insert_slot(&path, cursor, wanted),
+                }
+            };
+
+            self.place(container, key, wanted);
+            if wanted.itype == "submenu" {
+                self.reconcile(key, &wanted.submenu);
+            }
+            last = Some(key);
+        }
+    }
+
+    /// The keys of the items already in the menu that can stay where they are, which are the most items
+    /// of the template found there in the same order.
+    fn stable_keys(&self, container: usize, template: &[ElectronMenuItem]) -> HashSet<usize> {
+        let items = self.items_at(&self.path_of(container));
+        let found: Vec<(usize, usize)> = template
+            .iter()
+            .filter(|wanted| is_keyed(wanted))
+            .filter_map(|wanted| items.iter().position(|slot| !slot.placed && matches(&slot.item, wanted)).map(|index| (index, items[index].key)))
+            .collect();
+        let indexes: Vec<usize> = found.iter().map(|(index, _)| *index).collect();
+        longest_increasing(&indexes).into_iter().map(|position| found[position].1).collect()
+    }
+
+    /// Marks the item with the key as placed and updates the fields that differ from the template.
+    fn place(&mut self, container: usize, key: usize, wanted: &ElectronMenuItem) {
+        let path = self.path_of(container);
+        let items = self.items_at_mut(&path);
+        let Some(index) = items.iter().position(|slot| slot.key == key) else {
+            return;
+        };
+        items[index].placed = true;
+
+        let Some(patch) = patch_for(&items[index].item, wanted) else {
+            return;
+        };
+        patch.merge_at(items.iter_mut().map(|slot| &mut slot.item), index);
+        self.steps.push(Step::Update {
+            parent: path,
+            index,
+            patch,
+        });
+    }
+
+    fn insert_slot(&mut self, path: &[usize], index: usize, wanted: &ElectronMenuItem) -> usize {
+        let key = self.next_key();
+        let item = ElectronMenuItem {
+            submenu: Vec::new(),
+            ..wanted.clone()
+        };
+        self.steps.push(Step::Insert {
+            parent: path.to_vec(),
+            index,
+            item: item.clone(),
+        });
+        self.items_at_mut(path).insert(
+            index,
+            Slot {
+                key,
+                item,
+                items: Vec::new(),
+                placed: false,
+            },
+        );
+        key
+    }
+
+    /// Moves the item at the index of the menu at the path right after the item with the key last, or to the start.
+    fn move_slot(&mut self, container: usize, parent: Vec<usize>, index: usize, last: Option<usize>) -> usize {
+        let target = self.path_of(container);
+        let slot = self.items_at_mut(&parent).remove(index);
+        let key = slot.key;
+
+        // Removing the item may have shifted the menu and the item before the target index
+        let path = self.path_of(container);
+        let items = self.items_at_mut(&path);
+        let target_index = last.and_then(|last| items.iter().position(|slot| slot.key == last)).map_or(0, |index| index + 1);
+        items.insert(target_index, slot);
+
+        self.steps.push(Step::Move {
+            parent,
+            index,
+            target,
+            target_index,
+        });
+        key
+    }
+
+    /// The first item not placed yet that matches the template item, searching each menu before its submenus.
+    fn find_unplaced(&self, items: &[Slot], wanted: &ElectronMenuItem) -> Option<(Vec<usize>, usize)> {
+        if let Some(index) = items.iter().position(|slot| !slot.placed && matches(&slot.item, wanted)) {
+            return Some((Vec::new(), index));
+        }
+        items.iter().enumerate().find_map(|(index, slot)| {
+            self.find_unplaced(&slot.items, wanted).map(|(mut parent, found)| {
+                parent.insert(0, index);
+                (parent, found)
+            })
+        })
+    }
+
+    /// Removes the items that were not placed, from the last one so that the indexes of the others hold.
+    fn remove_unplaced(&mut self, path: Vec<usize>) {
+        for index in (0..self.items_at(&path).len()).rev() {
+            if self.items_at(&path)[index].placed {
+                let mut submenu = path.clone();
+                submenu.push(index);
+                self.remove_unplaced(submenu);
+            } else {
+                self.items_at_mut(&path).remove(index);
+                self.steps.push(Step::Remove {
+                    parent: path.clone(),
+                    index,
+                });
+            }
+        }
+    }
+
+    /// The indexes of the submenu items leading to the menu with the key.
+    fn path_of(&self, key: usize) -> Vec<usize> {
+        fn search(items: &[Slot], key: usize, path: &mut Vec<usize>) -> bool {
+            for (index, slot) in items.iter().enumerate() {
+                path.push(index);
+                if slot.key == key || search(&slot.items, key, path) {
+                    return true;
+                }
+                path.pop();
+            }
+            false
+        }
+
+        let mut path = Vec::new();
+        if key != ROOT {
+            search(&self.items, key, &mut path);
+        }
+        path
+    }
+
+    fn items_at(&self, path: &[usize]) -> &Vec<Slot> {
+        path.iter().fold(&self.items, |items, index| &items[*index].items)
+    }
+
+    fn items_at_mut(&mut self, path: &[usize]) -> &mut Vec<Slot> {
+        path.iter().fold(&mut self.items, |items, index| &mut items[*index].items)
+    }
+}
+
+/// Separators have no id, and items without one cannot be told apart by it.
+fn is_keyed(item: &ElectronMenuItem) -> bool {
+    item.itype != "separator" && !item.id.is_empty()
+}
+
+/// Whether the item can be kept for the template item. An item whose type changed is built again.
+fn matches(item: &ElectronMenuItem, wanted: &ElectronMenuItem) -> bool {
+    is_keyed(item) && item.id == wanted.id && item.itype == wanted.itype
+}
+
+/// The fields of the item that differ from the template item, leaving out those its type does not have.
+fn patch_for(item: &ElectronMenuItem, wanted: &ElectronMenuItem) -> Option<ItemPatch> {
+    let itype = item.itype.as_str();
+    let labeled = itype != "separator";
+    let checkable = itype == "checkbox" || itype == "radio";
+    let patch = ItemPatch {
+        label: (labeled && item.label != wanted.label).then(|| wanted.label.clone()),
+        enabled: (labeled && item.enabled != wanted.enabled).then_some(wanted.enabled),
+        checked: (checkable && item.checked != wanted.checked).then_some(wanted.checked),
+        accelerator: (labeled && itype != "submenu" && item.accelerator != wanted.accelerator).then(|| wanted.accelerator.clone()),
+        name: (itype == "radio" && item.name != wanted.name).then(|| wanted.name.clone()),
+    };
+
+    if patch.label.is_none() && patch.enabled.is_none() && patch.checked.is_none() && patch.accelerator.is_none() && patch.name.is_none() {
+        None
+    } else {
+        Some(patch)
+    }
+}
+
+/// The positions of a longest strictly increasing run of the values, in order.
+fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    // The position of the last value of the best run found for each length
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; values.len()];
+    for (position, value) in values.iter().enumerate() {
+        let length = tails.partition_point(|tail| values[*tail] < *value);
+        if length > 0 {
+            previous[position] = Some(tails[length - 1]);
+        }
+        if length == tails.len() {
+            tails.push(position);
+        } else {
+            tails[length] = position;
+        }
+    }
+
+    let mut run = Vec::new();
+    let mut position = tails.last().copied();
+    while let Some(current) = position {
+        run.push(current);
+        position = previous[current];
+    }
+    run.reverse();
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::test_items::*;
+
+    /// Diffs the items against the template, checking that the steps turn one into the other.
+    fn steps_for(items: &[ElectronMenuItem], template: &[ElectronMenuItem]) -> Vec<Step> {
+        let steps = diff(items.to_vec(), template);
+        let mut result = items.to_vec();
+        for step in &steps {
+            step.apply_to(&mut result).unwrap();
+        }
+        assert_eq!(entries(&result), entries(template));
+        steps
+    }
+
+    fn count(steps: &[Step]) -> (usize, usize, usize, usize) {
+        let mut counts = (0, 0, 0, 0);
+        for step in steps {
+            match step {
+                Step::Insert {
+                    ..
+                } => counts.0 += 1,
+                Step::Remove {
+                    ..
+                } => counts.1 += 1,
+                Step::Move {
+                    ..
+                } => counts.2 += 1,
+                Step::Update {
+                    ..
+                } => counts.3 += 1,
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn an_unchanged_template_takes_no_steps() {
+        let items = vec![text("a"), separator(), submenu("s", vec![text("b"), radio("r", true)])];
+        assert!(steps_for(&items, &items).is_empty());
+    }
+
+    #[test]
+    fn reordered_items_are_moved_around_the_ones_in_order() {
+        let items = vec![text("a"), text("b"), text("c"), text("d")];
+        // (inserts, removes, moves, updates)
+        assert_eq!(count(&steps_for(&items, &[text("b"), text("c"), text("d"), text("a")])), (0, 0, 1, 0));
+        assert_eq!(count(&steps_for(&items, &[text("d"), text("c"), text("b"), text("a")])), (0, 0, 3, 0));
+        assert_eq!(count(&steps_for(&items, &[text("a"), text("c"), text("b"), text("d")])), (0, 0, 1, 0));
+    }
+
+    #[test]
+    fn new_items_are_inserted_and_missing_ones_removed() {
+        let items = vec![text("a"), text("c"), text("d")];
+        let steps = steps_for(&items, &[text("a"), text("b"), text("c")]);
+        assert_eq!(count(&steps), (1, 1, 0, 0));
+        assert!(matches!(&steps[0], Step::Insert { parent, index: 1, item } if parent.is_empty() && item.id == "b"));
+        assert!(matches!(&steps[1], Step::Remove { parent, index: 3 } if parent.is_empty()));
+    }
+
+    #[test]
+    fn changed_fields_are_updated_in_place() {
+        let items = vec![text("a"), item("b", "checkbox")];
+        let template = vec![
+            ElectronMenuItem {
+                label: "Renamed".to_string(),
+                accelerator: "Ctrl+A".to_string(),
+                ..text("a")
+            },
+            ElectronMenuItem {
+                enabled: false,
+                checked: true,
+                ..item("b", "checkbox")
+            },
+        ];
+        let steps = steps_for(&items, &template);
+        assert_eq!(count(&steps), (0, 0, 0, 2));
+        assert!(matches!(&steps[0], Step::Update { index: 0, patch, .. }
+            if patch.label.as_deref() == Some("Renamed") && patch.accelerator.as_deref() == Some("Ctrl+A") && patch.enabled.is_none()));
+        assert!(matches!(&steps[1], Step::Update { index: 1, patch, .. } if patch.enabled == Some(false) && patch.checked == Some(true) && patch.label.is_none()));
+    }
+
+    #[test]
+    fn an_item_whose_type_changed_is_built_again() {
+        let steps = steps_for(&[text("a")], &[item("a", "checkbox")]);
+        assert_eq!(count(&steps), (1, 1, 0, 0));
+    }
+
+    #[test]
+    fn checking_a_radio_item_unchecks_the_rest_of_its_group() {
+        let items = vec![radio("x", false), radio("y", false), radio("z", true)];
+        let steps = steps_for(&items, &[radio("x", true), radio("y", false), radio("z", false)]);
+        // Checking x unchecks z, so z needs no update of its own
+        assert_eq!(count(&steps), (0, 0, 0, 1));
+    }
+
+    #[test]
+    fn separators_and_items_without_ids_are_matched_by_position() {
+        let items = vec![text("a"), separator(), text(""), text("b")];
+        assert!(steps_for(&items, &items).is_empty());
+
+        let steps = steps_for(&items, &[text("a"), text(""), separator(), text("b")]);
+        assert_eq!(count(&steps).2, 0);
+    }
+
+    #[test]
+    fn nested_items_keep_their_submenus_when_moved() {
+        let items = vec![submenu("file", vec![text("open"), submenu("recent", vec![text("one"), text("two")])]), text("quit")];
+        let template = vec![submenu("recent", vec![text("two"), text("one")]), submenu("file", vec![text("open"), text("quit")])];
+        let steps = steps_for(&items, &template);
+        // Nothing is built again: recent and quit are moved, and one of the recent items is reordered
+        assert_eq!(count(&steps), (0, 0, 3, 0));
+    }
+
+    #[test]
+    fn items_move_out_of_a_submenu_that_is_removed() {
+        let items = vec![submenu("old", vec![text("keep"), text("drop")])];
+        let steps = steps_for(&items, &[text("keep")]);
+        assert_eq!(count(&steps), (0, 1, 1, 0));
+    }
+
+    #[test]
+    fn repeated_ids_in_the_menu_still_reach_the_template() {
+        let items = vec![text("a"), text("a"), text("b")];
+        steps_for(&items, &[text("b"), text("a")]);
+        steps_for(&items, &[submenu("s", vec![text("a")]), text("a")]);
+    }
+
+    #[test]
+    fn repeated_ids_in_the_template_are_reported() {
+        let template = vec![text("a"), submenu("s", vec![text("a"), separator(), separator()]), text("")];
+        let Err(MenuError::InvalidTemplate(diagnostics)) = check_ids(&template) else {
+            panic!("expected InvalidTemplate");
+        };
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].path, "template[1].submenu[0].id");
+        assert_eq!(diagnostics[0].message, "duplicate id \"a\"");
+
+        assert!(check_ids(&[text("a"), text(""), text("")]).is_ok());
+    }
+
+    #[test]
+    fn longest_increasing_finds_a_longest_run() {
+        assert_eq!(longest_increasing(&[]), Vec::<usize>::new());
+        assert_eq!(longest_increasing(&[0, 1, 2]), [0, 1, 2]);
+        assert_eq!(longest_increasing(&[3, 1, 2]), [1, 2]);
+        assert_eq!(longest_increasing(&[2, 1, 0]).len(), 1);
+        assert_eq!(longest_increasing(&[1, 3, 2, 4, 0]), [0, 2, 3]);
+        // Equal values do not make a run longer
+        assert_eq!(longest_increasing(&[1, 1, 1]).len(), 1);
+
+        let values = [5, 0, 9, 1, 7, 2, 8, 3];
+        let run = longest_increasing(&values);
+        assert_eq!(run.len(), 4);
+        assert!(run.windows(2).all(|pair| pair[0] < pair[1] && values[pair[0]] < values[pair[1]]));
+    }
+}
@@ -0,0 +1,428 @@
+use crate::error::Diagnostic;
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::value::RawValue;
+use std::{cell::RefCell, fmt, ops::Range};
+use toml_edit::{ImDocument, Item, Table};
+
+/// Where a value starts in the text it was parsed from. Lines and columns start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A template or config value independent of where it came from.
+///
+/// Values parsed from a file carry their position so that Diagnostics can point at them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub value: Value,
+    pub position: Option<Position>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Also stands for undefined.
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Node>),
+    /// Entries in source order.
+    Object(Vec<(String, Node)>),
+    /// A value that cannot appear in a template or config, described by its type.
+    Other(&'static str),
+}
+
+impl Node {
+    pub fn new(value: Value) -> Self {
+        Self {
+            value,
+            position: None,
+        }
+    }
+
+    /// Returns a property of an object, treating null as missing. A repeated key returns the last value, as JSON.parse does.
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        match &self.value {
+            Value::Object(entries) => entries.iter().rev().find(|(k, _)| k == key).map(|(_, node)| node).filter(|node| node.value != Value::Null),
+            _ => None,
+        }
+    }
+
+    pub fn is_object(&self) -> bool {
+        matches!(self.value, Value::Object(_))
+    }
+
+    /// Describes the value for a Diagnostic the way it would be written in JavaScript.
+    pub fn describe(&self) -> String {
+        match &self.value {
+            Value::Null => "null".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => format!("\"{}\"", s),
+            Value::Array(_) => "array".to_string(),
+            Value::Object(_) => "object".to_string(),
+            Value::Other(kind) => kind.to_string(),
+        }
+    }
+}
+
+/// Converts byte offsets to lines and columns.
+struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        Self {
+            starts,
+        }
+    }
+
+    fn position(&self, text: &str, offset: usize) -> Position {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.starts[line];
+        let offset = offset.min(text.len());
+        Position {
+            line: line + 1,
+            column: text.get(start..offset).map_or(offset - start, |prefix| prefix.chars().count()) + 1,
+        }
+    }
+}
+
+const MAX_DEPTH: usize = 128;
+
+/// Parses JSON, reporting the first syntax error with its position.
+pub fn parse_json(text: &str) -> Result<Node, Diagnostic> {
+    // serde_json rejects the byte order mark some editors write, JSON.parse callers usually strip it
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let source = JsonSource {
+        text,
+        lines: LineIndex::new(text),
+        error: RefCell::new(None),
+    };
+
+    let mut deserializer = serde_json::Deserializer::from_str(text);
+    let node = JsonSeed {
+        source: &source,
+        depth: 0,
+    }
+    .deserialize(&mut deserializer)
+    .and_then(|node| deserializer.end().map(|()| node));
+    node.map_err(|e| source.error.take().unwrap_or_else(|| source.syntax_error(&e)))
+}
+
+/// The text being parsed. serde_json does not say where a value starts, so each value is read as a RawValue first,
+/// whose offset in the text gives its position, and then parsed again for its contents.
+struct JsonSource<'a> {
+    text: &'a str,
+    lines: LineIndex,
+    /// An error found while reading a value, which serde_json could only report at the end of that value.
+    error: RefCell<Option<Diagnostic>>,
+}
+
+impl JsonSource<'_> {
+    fn position(&self, raw: &str) -> Position {
+        self.lines.position(self.text, raw.as_ptr() as usize - self.text.as_ptr() as usize)
+    }
+
+    fn syntax_error(&self, e: &serde_json::Error) -> Diagnostic {
+        let message = e.to_string();
+        // The message ends with the position, which the Diagnostic carries on its own
+        let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(message, _)| message);
+        // serde_json gives the byte column of the last byte it read, 0 when that was the newline ending the line before
+        let position = (e.line() > 0).then(|| {
+            let start = self.lines.starts[e.line() - 1];
+            self.lines.position(self.text, (start + e.column()).saturating_sub(1))
+        });
+        Diagnostic::new("", message).at(position)
+    }
+}
+
+struct JsonSeed<'s, 'a> {
+    source: &'s JsonSource<'a>,
+    depth: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for JsonSeed<'_, 'de> {
+    type Value = Node;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Node, D::Error> {
+        let raw = <&'de RawValue>::deserialize(deserializer)?;
+        let position = self.source.position(raw.get());
+        if self.depth > MAX_DEPTH {
+            self.source.error.replace(Some(Diagnostic::new("", "nested too deeply").at(Some(position))));
+            return Err(de::Error::custom("nested too deeply"));
+        }
+
+        let value = serde_json::Deserializer::from_str(raw.get()).deserialize_any(self).map_err(de::Error::custom)?;
+        Ok(Node {
+            value,
+            position: Some(position),
+        })
+    }
+}
+
+impl<'de> Visitor<'de> for JsonSeed<'_, 'de> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::Number(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element_seed(self.child())? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(key) = map.next_key::<String>()? {
+            entries.push((key, map.next_value_seed(self.child())?));
+        }
+        Ok(Value::Object(entries))
+    }
+}
+
+impl JsonSeed<'_, '_> {
+    fn child(&self) -> Self {
+        Self {
+            source: self.source,
+            depth: self.depth + 1,
+        }
+    }
+}
+
+/// Parses TOML, reporting the first syntax error with its position.
+pub fn parse_toml(text: &str) -> Result<Node, Diagnostic> {
+    let source = TomlSource {
+        text,
+        lines: LineIndex::new(text),
+    };
+    match ImDocument::parse(text) {
+        Ok(document) => Ok(Node {
+            value: source.table(document.as_table()),
+            position: Some(Position {
+                line: 1,
+                column: 1,
+            }),
+        }),
+        Err(e) => {
+            let message = e.message().trim().replace('\n', ", ");
+            Err(Diagnostic::new("", message).at(source.position(e.span())))
+        }
+    }
+}
+
+struct TomlSource<'a> {
+    text: &'a str,
+    lines: LineIndex,
+}
+
+impl TomlSource<'_> {
+    /// Implicit tables such as `a` in `[a.b]` have no span, and so no position.
+    fn position(&self, span: Option<Range<usize>>) -> Option<Position> {
+        span.map(|span| self.lines.position(self.text, span.start))
+    }
+
+    fn table(&self, table: &Table) -> Value {
+        Value::Object(table.iter().map(|(key, item)| (key.to_string(), self.item(item))).collect())
+    }
+
+    fn item(&self, item: &Item) -> Node {
+        let value = match item {
+            Item::None => Value::Null,
+            Item::Value(value) => return self.value(value),
+            Item::Table(table) => self.table(table),
+            Item::ArrayOfTables(tables) => Value::Array(
+                tables
+                    .iter()
+                    .map(|table| Node {
+                        value: self.table(table),
+                        position: self.position(table.span()),
+                    })
+                    .collect(),
+            ),
+        };
+        Node {
+            value,
+            position: self.position(item.span()),
+        }
+    }
+
+    fn value(&self, value: &toml_edit::Value) -> Node {
+        use toml_edit::Value as Toml;
+        let node = match value {
+            Toml::String(s) => Value::String(s.value().clone()),
+            Toml::Integer(i) => Value::Number(*i.value() as f64),
+            Toml::Float(f) => Value::Number(*f.value()),
+            Toml::Boolean(b) => Value::Bool(*b.value()),
+            Toml::Datetime(_) => Value::Other("datetime"),
+            Toml::Array(items) => Value::Array(items.iter().map(|item| self.value(item)).collect()),
+            Toml::InlineTable(table) => Value::Object(table.iter().map(|(key, value)| (key.to_string(), self.value(value))).collect()),
+        };
+        Node {
+            value: node,
+            position: self.position(value.span()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, column: usize) -> Option<Position> {
+        Some(Position {
+            line,
+            column,
+        })
+    }
+
+    fn entry<'a>(node: &'a Node, key: &str) -> &'a Node {
+        node.get(key).unwrap_or_else(|| panic!("missing {}", key))
+    }
+
+    fn items(node: &Node) -> &[Node] {
+        match &node.value {
+            Value::Array(items) => items,
+            _ => panic!("expected an array, got {}", node.describe()),
+        }
+    }
+
+    #[test]
+    fn json_values_carry_their_position() {
+        let text = "{\n  \"items\": [\n    {\"label\": \"Öffnen\", \"enabled\": false},\n    null\n  ]\n}";
+        let root = parse_json(text).unwrap();
+        assert_eq!(root.position, at(1, 1));
+
+        let list = entry(&root, "items");
+        assert_eq!(list.position, at(2, 12));
+        let first = &items(list)[0];
+        assert_eq!(first.position, at(3, 5));
+        assert_eq!(entry(first, "label").value, Value::String("Öffnen".to_string()));
+        // Columns count characters, not bytes
+        assert_eq!(entry(first, "enabled").position, at(3, 36));
+        assert_eq!(items(list)[1].value, Value::Null);
+    }
+
+    #[test]
+    fn json_keeps_key_order_and_repeated_keys() {
+        let root = parse_json(r#"{"b": 1, "a": 2, "b": 3}"#).unwrap();
+        let Value::Object(entries) = &root.value else {
+            panic!("expected an object");
+        };
+        let keys: Vec<_> = entries.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["b", "a", "b"]);
+        assert_eq!(entry(&root, "b").value, Value::Number(3.0));
+    }
+
+    #[test]
+    fn json_skips_a_byte_order_mark() {
+        let root = parse_json("\u{feff}{\"a\": true}").unwrap();
+        assert_eq!(entry(&root, "a").position, at(1, 7));
+    }
+
+    #[test]
+    fn malformed_json_reports_line_and_column() {
+        let e = parse_json("{\n  \"label\": \"Open\",\n  \"enabled\": tru\n}").unwrap_err();
+        assert_eq!(e.position, at(3, 17));
+        assert_eq!(e.message, "expected ident");
+        assert_eq!(e.path, "");
+
+        let e = parse_json("[\n  1,\n  2,\n]").unwrap_err();
+        assert_eq!(e.position, at(4, 1));
+        assert_eq!(e.message, "expected value");
+
+        let e = parse_json("{\"label\": \"Ü\" \"x\"}").unwrap_err();
+        assert_eq!(e.position, at(1, 15));
+
+        let e = parse_json("{} {}").unwrap_err();
+        assert_eq!(e.position, at(1, 4));
+        assert_eq!(e.message, "trailing characters");
+
+        let e = parse_json("{\"a\": [1, 2").unwrap_err();
+        assert_eq!(e.message, "EOF while parsing a list");
+        assert_eq!(e.position, at(1, 11));
+    }
+
+    #[test]
+    fn deeply_nested_json_is_rejected_where_it_gets_too_deep() {
+        let text = format!("{}{}", "[".repeat(MAX_DEPTH + 2), "]".repeat(MAX_DEPTH + 2));
+        let e = parse_json(&text).unwrap_err();
+        assert_eq!(e.message, "nested too deeply");
+        assert_eq!(e.position, at(1, MAX_DEPTH + 2));
+    }
+
+    #[test]
+    fn toml_values_carry_their_position() {
+        let text = "[[items]]\nlabel = \"Open\"\naccelerator = \"Ctrl+O\"\n\n[[items]]\ntype = \"separator\"\n";
+        let root = parse_toml(text).unwrap();
+        let list = items(entry(&root, "items"));
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].position, at(1, 1));
+        assert_eq!(entry(&list[0], "label").position, at(2, 9));
+        assert_eq!(entry(&list[0], "accelerator").value, Value::String("Ctrl+O".to_string()));
+        assert_eq!(entry(&list[1], "type").position, at(6, 8));
+    }
+
+    #[test]
+    fn toml_implicit_tables_have_no_position() {
+        let root = parse_toml("[theme.dark]\ncolor = 0x202020\nwhen = 1979-05-27\ninline = { a = [1.5] }\n").unwrap();
+        let theme = entry(&root, "theme");
+        assert_eq!(theme.position, None);
+
+        let dark = entry(theme, "dark");
+        assert_eq!(dark.position, at(1, 1));
+        assert_eq!(entry(dark, "color").value, Value::Number(f64::from(0x202020)));
+        assert_eq!(entry(dark, "when").value, Value::Other("datetime"));
+        assert_eq!(entry(dark, "inline").position, at(4, 10));
+        assert_eq!(items(entry(entry(dark, "inline"), "a"))[0].value, Value::Number(1.5));
+    }
+
+    #[test]
+    fn malformed_toml_reports_line_and_column() {
+        let e = parse_toml("[[items]]\nlabel = \"Open\nenabled = true\n").unwrap_err();
+        assert_eq!(e.position, at(2, 14));
+        assert_eq!(e.path, "");
+
+        let e = parse_toml("a = 1\na = 2\n").unwrap_err();
+        assert_eq!(e.position, at(2, 1));
+        assert!(e.message.contains("duplicate key"), "{}", e.message);
+        assert!(!e.message.contains('\n'));
+    }
+}
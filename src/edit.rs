@@ -0,0 +1,750 @@
+use crate::{
+    error::{Diagnostic, MenuError},
+    registry,
+    types::{ElectronMenuItem, ItemPatch},
+};
+use wcpopup::{Menu, MenuItem};
+
+/// A change to the items of a menu. Ids are looked up anywhere in the menu, indexes refer to the menu itself.
+pub enum Operation {
+    Append(ElectronMenuItem),
+    Insert(usize, ElectronMenuItem),
+    InsertBefore(String, ElectronMenuItem),
+    InsertAfter(String, ElectronMenuItem),
+    RemoveAt(usize),
+    RemoveById(String),
+    Update(String, ItemPatch),
+    Move {
+        id: String,
+        /// Id of the submenu item to move into, or None for the menu itself.
+        target: Option<String>,
+        index: usize,
+    },
+}
+
+/// A change to the items of a menu at known positions. Menus are given as the indexes of the submenu items
+/// leading to them from the menu being changed, as they are when the change is applied.
+pub enum Step {
+    /// Adds the item at the index, or at the end if the index is past it, along with the items of its submenu.
+    Insert {
+        parent: Vec<usize>,
+        index: usize,
+        item: ElectronMenuItem,
+    },
+    Remove {
+        parent: Vec<usize>,
+        index: usize,
+    },
+    /// The target is given as it is before the item is removed, and the target index as it is after.
+    Move {
+        parent: Vec<usize>,
+        index: usize,
+        target: Vec<usize>,
+        target_index: usize,
+    },
+    Update {
+        parent: Vec<usize>,
+        index: usize,
+        patch: ItemPatch,
+    },
+}
+
+/// What an operation did that the registry has to know about, and what it reports.
+pub enum Effect {
+    /// Submenus built for an added item, in the order submenus_of gives.
+    Added(Vec<Menu>),
+    Removed(Vec<Menu>),
+    /// Submenus of a moved item, before and after it was built again.
+    Moved(Vec<Menu>, Vec<Menu>),
+    Updated(MenuItem),
+}
+
+/// What an operation returns to JS.
+pub enum Output {
+    None,
+    /// Handle of the submenu of an added submenu item.
+    Submenu(u32),
+    Item(MenuItem),
+}
+
+impl Operation {
+    /// Applies the operation to the native menu. Nothing is changed if it fails.
+    pub fn apply(self, menu: &Menu) -> Result<Effect, MenuError> {
+        let mut items = ElectronMenuItem::from_menu_items(&menu.items());
+        self.resolve(&mut items)?.apply(menu)
+    }
+
+    /// Finds the step the operation takes on a description of the menu and applies it there,
+    /// failing before anything is changed if the operation does not fit the menu.
+    fn resolve(self, items: &mut Vec<ElectronMenuItem>) -> Result<Step, MenuError> {
+        let step = match self {
+            Operation::Append(item) => Step::Insert {
+                parent: Vec::new(),
+                index: items.len(),
+                item,
+            },
+            Operation::Insert(index, item) => Step::Insert {
+                parent: Vec::new(),
+                index,
+                item,
+            },
+            Operation::InsertBefore(id, item) => {
+                let (parent, index) = locate(items, &id).ok_or_else(|| item_not_found(&id))?;
+                Step::Insert {
+                    parent,
+                    index,
+                    item,
+                }
+            }
+            Operation::InsertAfter(id, item) => {
+                let (parent, index) = locate(items, &id).ok_or_else(|| item_not_found(&id))?;
+                Step::Insert {
+                    parent,
+                    index: index + 1,
+                    item,
+                }
+            }
+            Operation::RemoveAt(index) => {
+                if index >= items.len() {
+                    return Err(index_out_of_range(index));
+                }
+                Step::Remove {
+                    parent: Vec::new(),
+                    index,
+                }
+            }
+            Operation::RemoveById(id) => {
+                let (parent, index) = locate(items, &id).ok_or_else(|| item_not_found(&id))?;
+                Step::Remove {
+                    parent,
+                    index,
+                }
+            }
+            Operation::Update(id, patch) => {
+                let (parent, index) = locate(items, &id).ok_or_else(|| item_not_found(&id))?;
+                patch.check(&container(items, &parent)[index].itype)?;
+                Step::Update {
+                    parent,
+                    index,
+                    patch,
+                }
+            }
+            Operation::Move {
+                id,
+                target,
+                index,
+            } => {
+                let (parent, current) = locate(items, &id).ok_or_else(|| item_not_found(&id))?;
+                let target = match target {
+                    None => Vec::new(),
+                    Some(target) => {
+                        let moved = &container(items, &parent)[current];
+                        if target == id || locate(&moved.submenu, &target).is_some() {
+                            return Err(cannot_move_into_itself(&id));
+                        }
+                        let (mut path, target_index) = locate(items, &target).ok_or_else(|| item_not_found(&target))?;
+                        if container(items, &path)[target_index].itype != "submenu" {
+                            return Err(not_a_submenu(&target));
+                        }
+                        path.push(target_index);
+                        path
+                    }
+                };
+                Step::Move {
+                    parent,
+                    index: current,
+                    target,
+                    target_index: index,
+                }
+            }
+        };
+        step.apply_to(items)?;
+        Ok(step)
+    }
+}
+
+impl Step {
+    /// Applies the step to the native menu.
+    fn apply(self, menu: &Menu) -> Result<Effect, MenuError> {
+        match self {
+            Step::Insert {
+                parent,
+                index,
+                item,
+            } => Ok(Effect::Added(add_item(submenu_at(menu, &parent)?, index, item.to_native(), &item.submenu))),
+            Step::Remove {
+                parent,
+                index,
+            } => {
+                let parent = submenu_at(menu, &parent)?;
+                let item = item_at(&parent, index)?;
+                Ok(remove_item(parent, index, &item))
+            }
+            Step::Move {
+                parent,
+                index,
+                target,
+                target_index,
+            } => {
+                let parent = submenu_at(menu, &parent)?;
+                let item = item_at(&parent, index)?;
+                let target = submenu_at(menu, &target)?;
+                Ok(move_to(parent, index, &item, target, target_index))
+            }
+            Step::Update {
+                parent,
+                index,
+                patch,
+            } => {
+                let parent = submenu_at(menu, &parent)?;
+                let item = item_at(&parent, index)?;
+                patch_at(parent.clone(), index, item, &patch);
+                item_at(&parent, index).map(Effect::Updated)
+            }
+        }
+    }
+
+    /// Applies the step to a description of the menu, failing exactly where apply would.
+    pub fn apply_to(&self, items: &mut Vec<ElectronMenuItem>) -> Result<(), MenuError> {
+        match self {
+            Step::Insert {
+                parent,
+                index,
+                item,
+            } => {
+                let parent = submenu_items(items, parent)?;
+                parent.insert((*index).min(parent.len()), item.clone());
+            }
+            Step::Remove {
+                parent,
+                index,
+            } => {
+                let parent = submenu_items(items, parent)?;
+                if *index >= parent.len() {
+                    return Err(out_of_step());
+                }
+                parent.remove(*index);
+            }
+            Step::Move {
+                parent,
+                index,
+                target,
+                target_index,
+            } => {
+                submenu_items(items, target)?;
+                let target = after_removal(target, parent, *index).ok_or_else(out_of_step)?;
+                let parent = submenu_items(items, parent)?;
+                if *index >= parent.len() {
+                    return Err(out_of_step());
+                }
+                let moved = parent.remove(*index);
+                let target = submenu_items(items, &target)?;
+                target.insert((*target_index).min(target.len()), moved);
+            }
+            Step::Update {
+                parent,
+                index,
+                patch,
+            } => {
+                let parent = submenu_items(items, parent)?;
+                if *index >= parent.len() {
+                    return Err(out_of_step());
+                }
+                patch.merge_at(parent.iter_mut(), *index);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Effect {
+    /// Records the submenus the operation added or removed in the registry.
+    pub fn record(self, menu_handle: f64) -> Result<Output, MenuError> {
+        match self {
+            Effect::Added(submenus) if submenus.is_empty() => Ok(Output::None),
+            Effect::Added(submenus) => {
+                let ids = registry::add_submenus(menu_handle, submenus)?;
+                Ok(Output::Submenu(ids[ids.len() - 1]))
+            }
+            Effect::Removed(submenus) => {
+                registry::remove_submenus(menu_handle, &submenus)?;
+                Ok(Output::None)
+            }
+            Effect::Moved(previous, rebuilt) => {
+                // A moved submenu is built again, so its handle is pointed at the new one
+                registry::rebind_submenus(&previous, rebuilt);
+                Ok(Output::None)
+            }
+            Effect::Updated(item) => Ok(Output::Item(item)),
+        }
+    }
+}
+
+/// Applies all operations, or none if any of them would fail.
+///
+/// The operations are first applied to a description of the menu, since wcpopup cannot undo a change. That also
+/// finds the items they refer to, so the native menu is then changed in one pass without looking them up again.
+pub fn apply_all(menu: &Menu, operations: Vec<Operation>) -> Result<Vec<Effect>, MenuError> {
+    let steps = resolve_all(ElectronMenuItem::from_menu_items(&menu.items()), operations)?;
+    steps.into_iter().map(|step| step.apply(menu)).collect()
+}
+
+fn resolve_all(mut items: Vec<ElectronMenuItem>, operations: Vec<Operation>) -> Result<Vec<Step>, MenuError> {
+    operations.into_iter().enumerate().map(|(index, operation)| operation.resolve(&mut items).map_err(|e| in_operation(e, index))).collect()
+}
+
+/// Applies the steps in order, or none of them if any would fail.
+///
+/// The steps are first tried on a description of the menu, since wcpopup cannot undo a change.
+pub fn apply_steps(menu: &Menu, steps: Vec<Step>) -> Result<Vec<Effect>, MenuError> {
+    try_steps(ElectronMenuItem::from_menu_items(&menu.items()), &steps)?;
+    steps.into_iter().map(|step| step.apply(menu)).collect()
+}
+
+fn try_steps(mut items: Vec<ElectronMenuItem>, steps: &[Step]) -> Result<(), MenuError> {
+    steps.iter().try_for_each(|step| step.apply_to(&mut items))
+}
+
+/// Tells which operation of a batch failed.
+fn in_operation(e: MenuError, index: usize) -> MenuError {
+    match e {
+        MenuError::InvalidArgument(message) => MenuError::InvalidArgument(format!("ops[{}]: {}", index, message)),
+        MenuError::InvalidTemplate(diagnostics) => MenuError::InvalidTemplate(
+            diagnostics
+                .into_iter()
+                .map(|diagnostic| Diagnostic {
+                    path: format!("ops[{}].{}", index, diagnostic.path),
+                    ..diagnostic
+                })
+                .collect(),
+        ),
+        e => e,
+    }
+}
+
+/// Adds the item at the index, or at the end if the index is past it, builds its submenu from the template
+/// and returns every submenu created in the order submenus_of gives. Items keep the uuids they are described with.
+fn add_item(mut parent: Menu, index: usize, mut native: MenuItem, submenu: &[ElectronMenuItem]) -> Vec<Menu> {
+    // wcpopup lays out the items a submenu item carries once, when it builds the submenu. It can only build
+    // submenu items that already have a submenu though, so those are added to it afterwards.
+    for item in submenu.iter().filter(|item| item.itype != "submenu") {
+        native.add_menu_item(item.to_native());
+    }
+
+    let len = parent.items().len();
+    let index = if index < len {
+        parent.insert(native, index as u32);
+        index
+    } else {
+        // Windows does not accept an index past the end
+        parent.append(native);
+        len
+    };
+
+    let Some(built) = parent.items().get(index).and_then(|item| item.submenu.clone()) else {
+        return Vec::new();
+    };
+    let mut submenus = Vec::new();
+    for (index, item) in submenu.iter().enumerate().filter(|(_, item)| item.itype == "submenu") {
+        submenus.extend(add_item(built.clone(), index, item.to_native(), &item.submenu));
+    }
+    submenus.push(built);
+    submenus
+}
+
+/// Adds the items of a menu just built from them again, so that they get the uuids they are described with, and
+/// returns the submenus of the menu in the order submenus_of gives.
+///
+/// MenuBuilder gives every item a new uuid. The menu is built with it all the same, since it is what sets up the
+/// accelerators that items added later are registered with.
+pub fn restore_uuids(menu: &Menu, items: &[ElectronMenuItem]) -> Vec<Menu> {
+    for (index, item) in items.iter().enumerate().filter(|(_, item)| item.uuid.is_some()) {
+        let mut parent = menu.clone();
+        parent.remove_at(index as u32);
+        add_item(parent, index, item.to_native(), &item.submenu);
+    }
+
+    let mut submenus = Vec::new();
+    registry::submenus_of(menu, &mut submenus);
+    submenus
+}
+
+fn remove_item(mut parent: Menu, index: usize, item: &MenuItem) -> Effect {
+    let removed = submenus_of_item(item);
+    parent.remove_at(index as u32);
+    Effect::Removed(removed)
+}
+
+/// Applies a patch that suits the type of the item at the index.
+fn patch_at(mut parent: Menu, index: usize, mut item: MenuItem, patch: &ItemPatch) {
+    if patch.needs_replace(&item) {
+        let mut replacement = ElectronMenuItem::from_menu_items(std::slice::from_ref(&item)).remove(0);
+        patch.merge(&mut replacement);
+        parent.remove_at(index as u32);
+        parent.insert(replacement.to_native(), index as u32);
+    } else {
+        if let Some(label) = &patch.label {
+            item.set_label(label);
+        }
+        if let Some(enabled) = patch.enabled {
+            item.set_disabled(!enabled);
+        }
+        if let Some(checked) = patch.checked.filter(|checked| *checked != item.checked) {
+            item.set_checked(checked);
+        }
+    }
+}
+
+/// Removes the item at the index and builds it again at the target index, counted once it is removed,
+/// since wcpopup cannot move an item.
+fn move_to(mut parent: Menu, index: usize, item: &MenuItem, target: Menu, target_index: usize) -> Effect {
+    let previous = submenus_of_item(item);
+    let moved = ElectronMenuItem::from_menu_items(std::slice::from_ref(item)).remove(0);
+    parent.remove_at(index as u32);
+
+    let rebuilt = add_item(target, target_index, moved.to_native(), &moved.submenu);
+    Effect::Moved(previous, rebuilt)
+}
+
+fn submenu_at(menu: &Menu, path: &[usize]) -> Result<Menu, MenuError> {
+    path.iter().try_fold(menu.clone(), |menu, index| item_at(&menu, *index)?.submenu.ok_or_else(out_of_step))
+}
+
+fn item_at(menu: &Menu, index: usize) -> Result<MenuItem, MenuError> {
+    menu.items().get(index).cloned().ok_or_else(out_of_step)
+}
+
+/// The items of the submenu at the path, found as submenu_at finds the submenu.
+fn submenu_items<'a>(items: &'a mut Vec<ElectronMenuItem>, path: &[usize]) -> Result<&'a mut Vec<ElectronMenuItem>, MenuError> {
+    path.iter().try_fold(items, |items, index| match items.get_mut(*index) {
+        Some(item) if item.itype == "submenu" => Ok(&mut item.submenu),
+        _ => Err(out_of_step()),
+    })
+}
+
+/// The path of a menu once the item at the index of the menu at the parent path is removed,
+/// or None if the menu is the submenu of that item or inside it.
+fn after_removal(path: &[usize], parent: &[usize], index: usize) -> Option<Vec<usize>> {
+    let mut path = path.to_vec();
+    if path.len() > parent.len() && path.starts_with(parent) {
+        let shifted = &mut path[parent.len()];
+        if *shifted == index {
+            return None;
+        }
+        if *shifted > index {
+            *shifted -= 1;
+        }
+    }
+    Some(path)
+}
+
+/// Finds the item with the id in the items or their submenus, returning the indexes of the submenu items leading to it
+/// along with its index.
+fn locate(items: &[ElectronMenuItem], id: &str) -> Option<(Vec<usize>, usize)> {
+    if id.is_empty() {
+        return None;
+    }
+    if let Some(index) = items.iter().position(|item| item.id == id) {
+        return Some((Vec::new(), index));
+    }
+    items.iter().enumerate().find_map(|(index, item)| {
+        locate(&item.submenu, id).map(|(mut parents, found)| {
+            parents.insert(0, index);
+            (parents, found)
+        })
+    })
+}
+
+/// The items of the submenu reached through the indexes.
+fn container<'a>(items: &'a mut Vec<ElectronMenuItem>, parents: &[usize]) -> &'a mut Vec<ElectronMenuItem> {
+    parents.iter().fold(items, |items, index| &mut items[*index].submenu)
+}
+
+/// The submenu of a submenu item along with the submenus nested in it, in the order submenus_of gives.
+fn submenus_of_item(item: &MenuItem) -> Vec<Menu> {
+    let mut submenus = Vec::new();
+    if let Some(submenu) = &item.submenu {
+        registry::submenus_of(submenu, &mut submenus);
+        submenus.push(submenu.clone());
+    }
+    submenus
+}
+
+fn item_not_found(id: &str) -> MenuError {
+    MenuError::InvalidArgument(format!("No item with id \"{}\"", id))
+}
+
+fn index_out_of_range(index: usize) -> MenuError {
+    MenuError::InvalidArgument(format!("No item at index {}", index))
+}
+
+fn cannot_move_into_itself(id: &str) -> MenuError {
+    MenuError::InvalidArgument(format!("Cannot move \"{}\" into itself", id))
+}
+
+fn not_a_submenu(id: &str) -> MenuError {
+    MenuError::InvalidArgument(format!("Item \"{}\" is not a submenu", id))
+}
+
+fn out_of_step() -> MenuError {
+    MenuError::BackendFailure("the menu no longer matches the items it was compared with".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::test_items::*;
+
+    /// a s[b t[c]] d
+    fn menu() -> Vec<ElectronMenuItem> {
+        vec![text("a"), submenu("s", vec![text("b"), submenu("t", vec![text("c")])]), text("d")]
+    }
+
+    /// Writes the ids of the items, with the items of submenus in brackets.
+    fn ids(items: &[ElectronMenuItem]) -> String {
+        items
+            .iter()
+            .map(|item| match item.itype.as_str() {
+                "submenu" => format!("{}[{}]", item.id, ids(&item.submenu)),
+                "separator" => "-".to_string(),
+                _ => item.id.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Gives the items uuids counting up from next, depth first.
+    fn numbered(items: &mut [ElectronMenuItem], next: &mut u16) {
+        for item in items {
+            item.uuid = Some(*next);
+            *next += 1;
+            numbered(&mut item.submenu, next);
+        }
+    }
+
+    /// Writes the uuids of the items the way ids writes their ids.
+    fn uuids(items: &[ElectronMenuItem]) -> String {
+        items
+            .iter()
+            .map(|item| {
+                let uuid = item.uuid.map_or_else(|| "?".to_string(), |uuid| uuid.to_string());
+                match item.itype.as_str() {
+                    "submenu" => format!("{}[{}]", uuid, uuids(&item.submenu)),
+                    _ => uuid,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Resolves the operation on the items, checking that its step makes the same change on its own,
+    /// as it does when it is applied to the native menu.
+    fn resolve(items: &[ElectronMenuItem], operation: Operation) -> Result<(String, Step), String> {
+        let mut resolved = items.to_vec();
+        let step = operation.resolve(&mut resolved).map_err(|e| e.to_string())?;
+        let mut stepped = items.to_vec();
+        step.apply_to(&mut stepped).unwrap();
+        assert_eq!(ids(&stepped), ids(&resolved));
+        Ok((ids(&resolved), step))
+    }
+
+    fn result(items: &[ElectronMenuItem], operation: Operation) -> String {
+        resolve(items, operation).map_or_else(|e| e, |(ids, _)| ids)
+    }
+
+    #[test]
+    fn items_are_added_where_the_native_menu_adds_them() {
+        assert_eq!(result(&menu(), Operation::Append(text("x"))), "a s[b t[c]] d x");
+        assert_eq!(result(&menu(), Operation::Insert(0, text("x"))), "x a s[b t[c]] d");
+        // An index past the end appends, as add_item does
+        assert_eq!(result(&menu(), Operation::Insert(9, text("x"))), "a s[b t[c]] d x");
+
+        let (ids, step) = resolve(&menu(), Operation::InsertBefore("c".to_string(), text("x"))).unwrap();
+        assert_eq!(ids, "a s[b t[x c]] d");
+        assert!(matches!(step, Step::Insert { parent, index: 0, .. } if parent == [1, 1]));
+        assert_eq!(result(&menu(), Operation::InsertAfter("b".to_string(), submenu("x", vec![text("y")]))), "a s[b x[y] t[c]] d");
+    }
+
+    #[test]
+    fn items_are_removed_by_index_or_anywhere_by_id() {
+        assert_eq!(result(&menu(), Operation::RemoveAt(1)), "a d");
+        let (ids, step) = resolve(&menu(), Operation::RemoveById("c".to_string())).unwrap();
+        assert_eq!(ids, "a s[b t[]] d");
+        assert!(matches!(step, Step::Remove { parent, index: 0 } if parent == [1, 1]));
+    }
+
+    #[test]
+    fn missing_items_and_indexes_are_reported() {
+        assert_eq!(result(&menu(), Operation::RemoveAt(3)), "Invalid argument: No item at index 3");
+        assert_eq!(result(&menu(), Operation::RemoveById("x".to_string())), "Invalid argument: No item with id \"x\"");
+        // Separators have no id to find them by
+        let items = vec![item("", "separator")];
+        assert_eq!(result(&items, Operation::InsertAfter(String::new(), text("x"))), "Invalid argument: No item with id \"\"");
+    }
+
+    #[test]
+    fn updates_check_the_fields_against_the_item_type() {
+        let patch = ItemPatch {
+            label: Some("Renamed".to_string()),
+            ..ItemPatch::default()
+        };
+        let mut items = menu();
+        Operation::Update("c".to_string(), patch).resolve(&mut items).unwrap();
+        assert_eq!(items[1].submenu[1].submenu[0].label, "Renamed");
+
+        let patch = ItemPatch {
+            checked: Some(true),
+            ..ItemPatch::default()
+        };
+        assert!(result(&menu(), Operation::Update("a".to_string(), patch)).contains("patch.checked: not supported by normal items"));
+    }
+
+    #[test]
+    fn moves_reach_into_submenus() {
+        let (ids, step) = resolve(
+            &menu(),
+            Operation::Move {
+                id: "a".to_string(),
+                target: Some("t".to_string()),
+                index: 1,
+            },
+        )
+        .unwrap();
+        assert_eq!(ids, "s[b t[c a]] d");
+        // The target path is taken before the item is removed
+        assert!(matches!(step, Step::Move { parent, index: 0, target, target_index: 1 } if parent.is_empty() && target == [1, 1]));
+
+        let to_top = Operation::Move {
+            id: "c".to_string(),
+            target: None,
+            index: 0,
+        };
+        assert_eq!(result(&menu(), to_top), "c a s[b t[]] d");
+    }
+
+    #[test]
+    fn moves_into_the_item_itself_or_a_plain_item_are_rejected() {
+        let moving = |id: &str, target: &str| Operation::Move {
+            id: id.to_string(),
+            target: Some(target.to_string()),
+            index: 0,
+        };
+        assert_eq!(result(&menu(), moving("s", "s")), "Invalid argument: Cannot move \"s\" into itself");
+        assert_eq!(result(&menu(), moving("s", "t")), "Invalid argument: Cannot move \"s\" into itself");
+        assert_eq!(result(&menu(), moving("b", "a")), "Invalid argument: Item \"a\" is not a submenu");
+        assert_eq!(result(&menu(), moving("b", "x")), "Invalid argument: No item with id \"x\"");
+    }
+
+    #[test]
+    fn batches_see_the_operations_before_them() {
+        let operations = vec![
+            Operation::Append(submenu("x", Vec::new())),
+            Operation::Move {
+                id: "a".to_string(),
+                target: Some("x".to_string()),
+                index: 0,
+            },
+            Operation::InsertAfter("a".to_string(), text("y")),
+        ];
+        let steps = resolve_all(menu(), operations).unwrap();
+
+        let mut items = menu();
+        for step in &steps {
+            step.apply_to(&mut items).unwrap();
+        }
+        assert_eq!(ids(&items), "s[b t[c]] d x[a y]");
+    }
+
+    #[test]
+    fn a_failing_operation_fails_the_batch_before_anything_is_applied() {
+        for failing in 0..3 {
+            let mut operations: Vec<_> = (0..3).map(|_| Operation::Append(text("x"))).collect();
+            operations[failing] = Operation::RemoveById("missing".to_string());
+            // Only the steps of a batch that resolved completely are applied to the native menu
+            let Err(e) = resolve_all(menu(), operations) else {
+                panic!("expected the batch to fail");
+            };
+            assert_eq!(e.to_string(), format!("Invalid argument: ops[{}]: No item with id \"missing\"", failing));
+        }
+
+        let patch = ItemPatch {
+            accelerator: Some("Ctrl+S".to_string()),
+            ..ItemPatch::default()
+        };
+        let Err(MenuError::InvalidTemplate(diagnostics)) = resolve_all(menu(), vec![Operation::RemoveAt(0), Operation::Update("s".to_string(), patch)]) else {
+            panic!("expected InvalidTemplate");
+        };
+        assert_eq!(diagnostics[0].path, "ops[1].patch.accelerator");
+    }
+
+    #[test]
+    fn steps_that_no_longer_fit_fail_before_any_is_applied() {
+        let items = vec![text("a"), submenu("s", vec![text("b")])];
+        let steps = vec![
+            Step::Remove {
+                parent: Vec::new(),
+                index: 0,
+            },
+            Step::Remove {
+                parent: vec![1],
+                index: 0,
+            },
+        ];
+        assert!(matches!(try_steps(items.clone(), &steps), Err(MenuError::BackendFailure(_))));
+
+        let into_itself = Step::Move {
+            parent: Vec::new(),
+            index: 1,
+            target: vec![1],
+            target_index: 0,
+        };
+        assert!(try_steps(items.clone(), &[into_itself]).is_err());
+
+        let into_text = Step::Insert {
+            parent: vec![0],
+            index: 0,
+            item: text("c"),
+        };
+        assert!(try_steps(items, &[into_text]).is_err());
+    }
+
+    #[test]
+    fn targets_shift_when_an_item_before_them_moves() {
+        assert_eq!(after_removal(&[2, 0], &[], 1), Some(vec![1, 0]));
+        assert_eq!(after_removal(&[0, 0], &[], 1), Some(vec![0, 0]));
+        assert_eq!(after_removal(&[1], &[], 1), None);
+        assert_eq!(after_removal(&[1, 3], &[1], 0), Some(vec![1, 2]));
+        assert_eq!(after_removal(&[], &[1], 0), Some(vec![]));
+        assert_eq!(after_removal(&[2], &[1], 0), Some(vec![2]));
+    }
+
+    #[test]
+    fn locate_searches_each_menu_before_its_submenus() {
+        let items = vec![submenu("s", vec![text("x")]), text("x")];
+        assert_eq!(locate(&items, "x"), Some((Vec::new(), 1)));
+        assert_eq!(locate(&menu(), "c"), Some((vec![1, 1], 0)));
+        assert_eq!(locate(&menu(), ""), None);
+        assert_eq!(ids(container(&mut menu(), &[1, 1])), "c");
+    }
+
+    #[test]
+    fn moved_items_end_up_at_the_index() {
+        let moving = |id: &str, target: Option<&str>, index: usize| Operation::Move {
+            id: id.to_string(),
+            target: target.map(str::to_string),
+            index,
+        };
+        // The index counts the items of the target once the moved item has left them
+        assert_eq!(result(&menu(), moving("a", None, 2)), "s[b t[c]] d a");
+        assert_eq!(result(&menu(), moving("d", None, 0)), "d a s[b t[c]]");
+        assert_eq!(result(&menu(), moving("b", Some("s"), 1)), "a s[t[c] b] d");
+        // An index past the end appends, as for inserts
+        assert_eq!(result(&menu(), moving("a", None, 9)), "s[b t[c]] d a");
+        // A moved submenu takes its items along, and they keep their uuids as the item does
+        assert_eq!(result(&menu(), moving("t", None, 0)), "t[c] a s[b] d");
+        let mut items = menu();
+        numbered(&mut items, &mut 1);
+        moving("s", None, 2).resolve(&mut items).unwrap();
+        assert_eq!(uuids(&items), "1 6 2[3 4[5]]");
+    }
+}
@@ -0,0 +1,164 @@
+use crate::document::Position;
+use neon::{
+    handle::Handle,
+    object::Object,
+    prelude::Context,
+    result::{JsResult, NeonResult},
+    types::{JsArray, JsError},
+};
+use std::fmt;
+
+/// A problem found at a JSON-pointer-like path, e.g. `template[3].submenu[1].type`.
+///
+/// Problems found in a file also carry the position of the value. Syntax errors have an empty path.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub path: String,
+    pub message: String,
+    pub position: Option<Position>,
+}
+
+impl Diagnostic {
+    pub fn new(path: &str, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_string(),
+            message: message.into(),
+            position: None,
+        }
+    }
+
+    pub fn at(mut self, position: Option<Position>) -> Self {
+        self.position = position;
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.path.is_empty(), self.position) {
+            (false, None) => write!(f, "{}: {}", self.path, self.message),
+            (false, Some(position)) => write!(f, "{} (line {}, column {}): {}", self.path, position.line, position.column, self.message),
+            (true, Some(position)) => write!(f, "line {}, column {}: {}", position.line, position.column, self.message),
+            (true, None) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+fn join_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    diagnostics.iter().map(|d| d.to_string()).collect::<Vec<String>>().join("\n")
+}
+
+pub fn from_diagnostics<'a, C: Context<'a>>(cx: &mut C, diagnostics: &[Diagnostic]) -> JsResult<'a, JsArray> {
+    let array = JsArray::new(cx, diagnostics.len());
+    for (index, diagnostic) in diagnostics.iter().enumerate() {
+        let obj = cx.empty_object();
+        let path = cx.string(&diagnostic.path);
+        obj.set(cx, "path", path)?;
+        let message = cx.string(&diagnostic.message);
+        obj.set(cx, "message", message)?;
+        if let Some(position) = diagnostic.position {
+            let line = cx.number(position.line as f64);
+            obj.set(cx, "line", line)?;
+            let column = cx.number(position.column as f64);
+            obj.set(cx, "column", column)?;
+        }
+        array.set(cx, index as u32, obj)?;
+    }
+    Ok(array)
+}
+
+/// Errors surfaced to JavaScript.
+///
+/// Each variant is thrown as an `Error` whose `name` and `code` stay stable across releases,
+/// so that lib/index.ts can map them to the exported Error subclasses.
+#[derive(Debug, Clone)]
+pub enum MenuError {
+    /// No menu is registered for the handle.
+    UnknownHandle(f64),
+    /// The menu is open and cannot be changed until it closes.
+    MenuBusy,
+    /// The menu template is malformed.
+    InvalidTemplate(Vec<Diagnostic>),
+    /// The menu config is malformed.
+    InvalidConfig(Vec<Diagnostic>),
+    /// The native menu could not be created or updated.
+    BackendFailure(String),
+    /// An argument is out of range or refers to the wrong kind of menu.
+    InvalidArgument(String),
+}
+
+impl MenuError {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MenuError::UnknownHandle(_) => "UnknownHandleError",
+            MenuError::MenuBusy => "MenuBusyError",
+            MenuError::InvalidTemplate(_) => "InvalidTemplateError",
+            MenuError::InvalidConfig(_) => "InvalidConfigError",
+            MenuError::BackendFailure(_) => "BackendFailureError",
+            MenuError::InvalidArgument(_) => "InvalidArgumentError",
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            MenuError::UnknownHandle(_) => "ERR_UNKNOWN_HANDLE",
+            MenuError::MenuBusy => "ERR_MENU_BUSY",
+            MenuError::InvalidTemplate(_) => "ERR_INVALID_TEMPLATE",
+            MenuError::InvalidConfig(_) => "ERR_INVALID_CONFIG",
+            MenuError::BackendFailure(_) => "ERR_BACKEND_FAILURE",
+            MenuError::InvalidArgument(_) => "ERR_INVALID_ARGUMENT",
+        }
+    }
+
+    /// Creates the JavaScript Error object without throwing it.
+    pub fn to_js_error<'a, C: Context<'a>>(&self, cx: &mut C) -> NeonResult<Handle<'a, JsError>> {
+        let error = cx.error(self.to_string())?;
+
+        let name = cx.string(self.name());
+        error.set(cx, "name", name)?;
+
+        let code = cx.string(self.code());
+        error.set(cx, "code", code)?;
+
+        if let MenuError::InvalidTemplate(diagnostics) | MenuError::InvalidConfig(diagnostics) = self {
+            let diagnostics = from_diagnostics(cx, diagnostics)?;
+            error.set(cx, "diagnostics", diagnostics)?;
+        }
+
+        Ok(error)
+    }
+
+    pub fn throw<'a, C: Context<'a>, T>(&self, cx: &mut C) -> NeonResult<T> {
+        let error = self.to_js_error(cx)?;
+        cx.throw(error)
+    }
+}
+
+impl fmt::Display for MenuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MenuError::UnknownHandle(handle) => write!(f, "Menu {} does not exist", handle),
+            MenuError::MenuBusy => write!(f, "Menu is busy"),
+            MenuError::InvalidTemplate(diagnostics) => write!(f, "Invalid template:\n{}", join_diagnostics(diagnostics)),
+            MenuError::InvalidConfig(diagnostics) => write!(f, "Invalid config:\n{}", join_diagnostics(diagnostics)),
+            MenuError::BackendFailure(message) => write!(f, "Native menu failure: {}", message),
+            MenuError::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for MenuError {}
+
+pub trait MenuResultExt<T> {
+    /// Throws the MenuError as a JavaScript exception.
+    fn or_throw<'a, C: Context<'a>>(self, cx: &mut C) -> NeonResult<T>;
+}
+
+impl<T> MenuResultExt<T> for Result<T, MenuError> {
+    fn or_throw<'a, C: Context<'a>>(self, cx: &mut C) -> NeonResult<T> {
+        match self {
+            Ok(value) => Ok(value),
+            Err(e) => e.throw(cx),
+        }
+    }
+}
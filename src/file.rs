@@ -0,0 +1,295 @@
+use crate::{
+    document::{self, Node, Value},
+    error::{Diagnostic, MenuError},
+    types::{ElectronMenuItem, StyleOptions},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+use wcpopup::config::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Self, MenuError> {
+        match name {
+            "json" => Ok(Format::Json),
+            "toml" => Ok(Format::Toml),
+            _ => Err(MenuError::InvalidArgument(format!("unknown format \"{}\"", name))),
+        }
+    }
+
+    /// Tells the format from the extension of the file.
+    pub fn from_path(path: &Path) -> Result<Self, MenuError> {
+        match path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_ascii_lowercase()).as_deref() {
+            Some("json") => Ok(Format::Json),
+            Some("toml") => Ok(Format::Toml),
+            _ => Err(MenuError::InvalidArgument(format!("Cannot tell the format of {}, pass the format option", path.display()))),
+        }
+    }
+}
+
+/// A template read from a JSON or TOML file, along with the config embedded in it.
+///
+/// A file is either an array of items or an object holding `template` and an optional `config`.
+/// TOML files are always objects, with the items written as `[[template]]` tables.
+/// The config is kept unread, since the base it is merged over depends on the build options.
+#[derive(Debug, Clone)]
+pub struct MenuFile {
+    pub template: Vec<ElectronMenuItem>,
+    pub config: Option<Node>,
+}
+
+impl MenuFile {
+    pub fn read(path: &Path, format: Option<Format>) -> Result<Self, MenuError> {
+        let format = match format {
+            Some(format) => format,
+            None => Format::from_path(path)?,
+        };
+        let text = fs::read_to_string(path).map_err(|e| MenuError::InvalidArgument(format!("Cannot read {}: {}", path.display(), e)))?;
+        Self::parse(&text, format)
+    }
+
+    /// Parses and checks the template. Syntax errors and template problems are reported as InvalidTemplate,
+    /// a config that is not an object as InvalidConfig.
+    pub fn parse(text: &str, format: Format) -> Result<Self, MenuError> {
+        let root = match format {
+            Format::Json => document::parse_json(text),
+            Format::Toml => document::parse_toml(text),
+        }
+        .map_err(|diagnostic| MenuError::InvalidTemplate(vec![diagnostic]))?;
+
+        let (items, config) = match &root.value {
+            Value::Array(items) => (items, None),
+            Value::Object(_) => match root.get("template") {
+                Some(Node {
+                    value: Value::Array(items),
+                    ..
+                }) => (items, root.get("config")),
+                Some(template) => {
+                    let message = format!("expected array, got {}", template.describe());
+                    return Err(MenuError::InvalidTemplate(vec![Diagnostic::new("template", message).at(template.position)]));
+                }
+                None => return Err(MenuError::InvalidTemplate(vec![Diagnostic::new("template", "missing").at(root.position)])),
+            },
+            _ => {
+                let message = format!("expected object or array, got {}", root.describe());
+                return Err(MenuError::InvalidTemplate(vec![Diagnostic::new("", message).at(root.position)]));
+            }
+        };
+
+        let mut diagnostics = Vec::new();
+        let template = ElectronMenuItem::from_nodes(items, "template", &mut diagnostics);
+        if !diagnostics.is_empty() {
+            return Err(MenuError::InvalidTemplate(diagnostics));
+        }
+
+        if let Some(config) = config.filter(|config| !config.is_object()) {
+            let message = format!("expected object, got {}", config.describe());
+            return Err(MenuError::InvalidConfig(vec![Diagnostic::new("config", message).at(config.position)]));
+        }
+
+        Ok(Self {
+            template,
+            config: config.cloned(),
+        })
+    }
+}
+
+/// A menu file along with the build options, so that the menu can be built again when the file changes.
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    pub path: PathBuf,
+    pub format: Option<Format>,
+    pub style: StyleOptions,
+}
+
+impl FileSource {
+    pub fn load(&self) -> Result<(Vec<ElectronMenuItem>, Config), MenuError> {
+        let file = MenuFile::read(&self.path, self.format)?;
+        let config = self.style.to_config(file.config.as_ref())?;
+        Ok((file.template, config))
+    }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Polls a file on a background thread until dropped.
+///
+/// Polling needs no platform support and is fast enough for a development mode.
+pub struct Watch {
+    stop: Arc<AtomicBool>,
+}
+
+impl Watch {
+    /// Calls back on the polling thread once a change has settled, so that a save written in steps is reported once.
+    pub fn start(path: PathBuf, changed: impl Fn() + Send + 'static) -> Self {
+        Self::poll(path, POLL_INTERVAL, changed)
+    }
+
+    fn poll(path: PathBuf, interval: Duration, changed: impl Fn() + Send + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        thread::spawn(move || {
+            let mut last = stamp(&path);
+            let mut pending = false;
+            loop {
+                thread::sleep(interval);
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let current = stamp(&path);
+                if current != last {
+                    last = current;
+                    pending = true;
+                } else if pending {
+                    pending = false;
+                    changed();
+                }
+            }
+        });
+        Self {
+            stop,
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A missing file has no stamp, so that deleting and restoring it counts as a change.
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, RecvTimeoutError};
+
+    const INTERVAL: Duration = Duration::from_millis(20);
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, text: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("wcpopup-node-{}-{}", std::process::id(), name));
+            fs::write(&path, text).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// The path, message, line and column of each problem found parsing the text.
+    fn problems(text: &str, format: Format) -> Vec<(String, String, usize, usize)> {
+        let diagnostics = match MenuFile::parse(text, format) {
+            Err(MenuError::InvalidTemplate(diagnostics) | MenuError::InvalidConfig(diagnostics)) => diagnostics,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("parsed {:?}", text),
+        };
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let position = diagnostic.position.expect("a position");
+                (diagnostic.path, diagnostic.message, position.line, position.column)
+            })
+            .collect()
+    }
+
+    fn problem(path: &str, message: &str, line: usize, column: usize) -> Vec<(String, String, usize, usize)> {
+        vec![(path.to_string(), message.to_string(), line, column)]
+    }
+
+    #[test]
+    fn a_missing_or_misshapen_template_is_reported_where_it_is() {
+        assert_eq!(problems("{\n  \"config\": {}\n}", Format::Json), problem("template", "missing", 1, 1));
+        assert_eq!(problems("{\n  \"template\": {\"label\": \"Open\"}\n}", Format::Json), problem("template", "expected array, got object", 2, 15));
+        assert_eq!(problems("template = \"Open\"\n", Format::Toml), problem("template", "expected array, got \"Open\"", 1, 12));
+    }
+
+    #[test]
+    fn a_config_that_is_not_an_object_is_reported_where_it_is() {
+        let text = "{\n  \"template\": [],\n  \"config\": [\"dark\"]\n}";
+        assert_eq!(problems(text, Format::Json), problem("config", "expected object, got array", 3, 13));
+    }
+
+    #[test]
+    fn a_root_that_is_neither_an_array_nor_an_object_is_reported_where_it_is() {
+        assert_eq!(problems("\n  \"Open\"", Format::Json), problem("", "expected object or array, got \"Open\"", 2, 3));
+        assert_eq!(problems("42", Format::Json), problem("", "expected object or array, got 42", 1, 1));
+    }
+
+    #[test]
+    fn toml_without_template_tables_is_reported() {
+        let text = "[config]\ntheme = \"dark\"\n";
+        assert_eq!(problems(text, Format::Toml), problem("template", "missing", 1, 1));
+    }
+
+    fn labels(source: &FileSource) -> Vec<String> {
+        source.load().unwrap().0.into_iter().map(|item| item.label).collect()
+    }
+
+    #[test]
+    fn a_change_is_reported_once_and_loads_the_new_template() {
+        let file = TempFile::new("change.json", r#"[{"label": "Open"}]"#);
+        let source = FileSource {
+            path: file.0.clone(),
+            format: None,
+            style: StyleOptions::default(),
+        };
+        assert_eq!(labels(&source), ["Open"]);
+
+        let (sender, receiver) = mpsc::channel();
+        let _watch = Watch::poll(file.0.clone(), INTERVAL, move || sender.send(()).unwrap());
+        thread::sleep(INTERVAL * 3);
+        fs::write(&file.0, r#"[{"label": "Open"}, {"label": "Save"}]"#).unwrap();
+
+        receiver.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(labels(&source), ["Open", "Save"]);
+        assert_eq!(receiver.recv_timeout(INTERVAL * 10), Err(RecvTimeoutError::Timeout));
+    }
+
+    #[test]
+    fn deleting_the_file_counts_as_a_change() {
+        let file = TempFile::new("delete.toml", "[[template]]\nlabel = \"Open\"\n");
+        let (sender, receiver) = mpsc::channel();
+        let _watch = Watch::poll(file.0.clone(), INTERVAL, move || sender.send(()).unwrap());
+        thread::sleep(INTERVAL * 3);
+        fs::remove_file(&file.0).unwrap();
+        receiver.recv_timeout(TIMEOUT).unwrap();
+    }
+
+    #[test]
+    fn dropping_the_watch_stops_the_thread() {
+        let file = TempFile::new("drop.json", "[]");
+        let (sender, receiver) = mpsc::channel::<()>();
+        let watch = Watch::poll(file.0.clone(), INTERVAL, move || sender.send(()).unwrap());
+        drop(watch);
+        fs::write(&file.0, r#"[{"label": "Changed"}]"#).unwrap();
+
+        // The sender is dropped along with the thread, without reporting the change
+        assert_eq!(receiver.recv_timeout(TIMEOUT), Err(RecvTimeoutError::Disconnected));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{
+    error::MenuError,
+    release,
+    types::{describe, is_nullish},
+};
+use neon::{
+    handle::Handle,
+    object::Object,
+    prelude::{Context, FunctionContext},
+    result::NeonResult,
+    types::{buffer::TypedArray, Finalize, JsBoolean, JsBox, JsBuffer, JsNumber, JsObject, JsValue},
+};
+use std::cell::Cell;
+
+/// Native handle owned by a JS object. The menu is destroyed when the object is garbage collected.
+pub struct MenuBox {
+    menu_handle: Cell<f64>,
+}
+
+impl MenuBox {
+    pub fn new(menu_handle: f64) -> Self {
+        Self {
+            menu_handle: Cell::new(menu_handle),
+        }
+    }
+
+    pub fn menu_handle(&self) -> f64 {
+        self.menu_handle.get()
+    }
+
+    /// Detaches the box from a menu that was destroyed explicitly.
+    pub fn forget(&self) {
+        self.menu_handle.set(0.0);
+    }
+}
+
+impl Finalize for MenuBox {
+    fn finalize<'a, C: Context<'a>>(self, _cx: &mut C) {
+        let menu_handle = self.menu_handle.get();
+        if menu_handle != 0.0 {
+            release(menu_handle);
+        }
+    }
+}
+
+/// Reads a menu handle argument given either as a number or as a boxed handle.
+pub fn menu_handle_arg(cx: &mut FunctionContext, i: usize) -> NeonResult<f64> {
+    let value = cx.argument::<JsValue>(i)?;
+
+    if let Ok(boxed) = value.downcast::<JsBox<MenuBox>, _>(cx) {
+        return Ok(boxed.menu_handle());
+    }
+
+    Ok(value.downcast_or_throw::<JsNumber, _>(cx)?.value(cx))
+}
+
+/// Reads the parent window argument. See `to_parent`.
+pub fn parent_arg(cx: &mut FunctionContext, i: usize) -> NeonResult<u64> {
+    let value = cx.argument::<JsValue>(i)?;
+    to_parent(cx, value)
+}
+
+/// Reads the parent window given either as a number or as the Buffer returned by `getNativeWindowHandle()`.
+/// The Buffer holds the XID or HWND in native byte order.
+pub fn to_parent(cx: &mut FunctionContext, value: Handle<JsValue>) -> NeonResult<u64> {
+    let parent = if let Ok(buffer) = value.downcast::<JsBuffer, _>(cx) {
+        let bytes = buffer.as_slice(cx);
+        match bytes.len() {
+            4 => u32::from_ne_bytes(bytes.try_into().unwrap()) as u64,
+            8 => u64::from_ne_bytes(bytes.try_into().unwrap()),
+            len => return MenuError::InvalidArgument(format!("Window handle Buffer must be 4 or 8 bytes, got {}", len)).throw(cx),
+        }
+    } else {
+        let number = value.downcast_or_throw::<JsNumber, _>(cx)?.value(cx);
+        if !number.is_finite() || number.fract() != 0.0 {
+            return MenuError::InvalidArgument(format!("Window handle must be an integer, got {}", number)).throw(cx);
+        }
+        // HWNDs read with readInt32 may be negative and are sign extended
+        number as i64 as u64
+    };
+
+    if parent == 0 {
+        return MenuError::InvalidArgument("Window handle must not be zero".to_string()).throw(cx);
+    }
+
+    Ok(parent)
+}
+
+/// Returns the boxed handle given as an argument, if any.
+pub fn menu_box_arg<'a>(cx: &mut FunctionContext<'a>, i: usize) -> Option<Handle<'a, JsBox<MenuBox>>> {
+    cx.argument_opt(i).and_then(|value| value.downcast::<JsBox<MenuBox>, _>(cx).ok())
+}
+
+/// Reads `boxed` from build options. Left out, it is false; any value other than a boolean is rejected.
+pub fn to_boxed(cx: &mut FunctionContext, options: &Handle<JsObject>) -> NeonResult<bool> {
+    let boxed = options.get_value(cx, "boxed")?;
+    if let Ok(boxed) = boxed.downcast::<JsBoolean, _>(cx) {
+        return Ok(boxed.value(cx));
+    }
+    if is_nullish(cx, boxed) {
+        return Ok(false);
+    }
+    let got = describe(cx, boxed);
+    MenuError::InvalidArgument(format!("boxed must be a boolean, got {}", got)).throw(cx)
+}
@@ -1,101 +1,228 @@
-use async_std::sync::Mutex;
 #[cfg(target_os = "linux")]
 use gdkx11::ffi::{gdk_x11_get_default_xdisplay, gdk_x11_lookup_xdisplay, gdk_x11_window_foreign_new_for_display};
 #[cfg(target_os = "linux")]
 use gtk::{
-    gdk::Window,
-    glib::{self, translate::FromGlibPtrFull},
-    prelude::WidgetExt,
+    gdk::{self, Window},
+    glib::{
+        self,
+        translate::{FromGlibPtrFull, FromGlibPtrNone, ToGlibPtr},
+        Cast,
+    },
+    prelude::{ContainerExt, GtkMenuExt, GtkMenuItemExt, WidgetExt, WidgetExtManual},
 };
 use neon::{
-    handle::Handle,
+    handle::{Handle, Root},
+    object::Object,
     prelude::{Context, FunctionContext, ModuleContext},
     result::{JsResult, NeonResult},
-    types::{JsArray, JsNumber, JsObject, JsPromise, JsString, JsUndefined, JsValue},
+    types::{JsArray, JsBigInt, JsBoolean, JsFunction, JsNumber, JsObject, JsPromise, JsString, JsUndefined, JsValue},
 };
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use wcpopup::{
-    config::{Config, Theme},
-    Menu, MenuBuilder,
+use std::{path::PathBuf, sync::Arc};
+use wcpopup::{config::Config, Menu, MenuBuilder, MenuItem, MenuType};
+#[cfg(target_os = "windows")]
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, WPARAM},
+    UI::{
+        Input::KeyboardAndMouse::VK_ESCAPE,
+        WindowsAndMessaging::{DestroyWindow, SendMessageW, WM_KEYDOWN},
+    },
 };
+mod color;
+mod diff;
+mod document;
+mod edit;
+mod error;
+mod file;
+mod handle;
+mod outcome;
+mod registry;
+mod theme;
 mod types;
+use edit::Operation;
+use error::*;
+use file::{FileSource, Format, Watch};
+use handle::*;
+use outcome::Outcome;
+use registry::{MenuState, Popup};
+use theme::Preset;
 use types::*;
 
-static MENU_MAP: Lazy<Mutex<HashMap<i32, Menu>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+pub fn build_menu_from_options(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let options = cx.argument::<JsObject>(0)?;
+    let options = to_build_options(&mut cx, options)?;
+    build_with_options(&mut cx, options)
+}
 
-pub fn build_from_template(mut cx: FunctionContext) -> JsResult<JsNumber> {
-    if cx.len() != 2 {
+pub fn build_from_template(mut cx: FunctionContext) -> JsResult<JsValue> {
+    if !(2..=3).contains(&cx.len()) {
         return cx.throw_error("Invalid number of arguments");
     }
 
-    let parent = cx.argument::<JsNumber>(0)?.value(&mut cx);
-    let templates = cx.argument::<JsArray>(1)?.to_vec(&mut cx).unwrap();
+    let options = BuildOptions {
+        parent: parent_arg(&mut cx, 0)?,
+        template: cx.argument::<JsArray>(1)?.to_vec(&mut cx)?,
+        config: Config::default(),
+        boxed: TrailingOptions::read(&mut cx, 2)?.boxed,
+    };
 
-    let menu_handle = build(&mut cx, parent, templates, Config::default());
-    let id = cx.number(menu_handle as i32);
-    Ok(id)
+    build_with_options(&mut cx, options)
 }
 
-pub fn build_from_template_with_theme(mut cx: FunctionContext) -> JsResult<JsNumber> {
-    if cx.len() != 3 {
+pub fn build_from_template_with_theme(mut cx: FunctionContext) -> JsResult<JsValue> {
+    if !(3..=4).contains(&cx.len()) {
         return cx.throw_error("Invalid number of arguments");
     }
 
-    let parent = cx.argument::<JsNumber>(0)?.value(&mut cx);
-    let templates = cx.argument::<JsArray>(1)?.to_vec(&mut cx).unwrap();
-    let theme_str = cx.argument::<JsString>(2)?.value(&mut cx);
+    let theme = cx.argument::<JsString>(2)?.value(&mut cx);
+    let trailing = TrailingOptions::read(&mut cx, 3)?;
+    let options = BuildOptions {
+        parent: parent_arg(&mut cx, 0)?,
+        template: cx.argument::<JsArray>(1)?.to_vec(&mut cx)?,
+        config: theme::to_preset(&theme).into_config(),
+        boxed: trailing.boxed,
+    };
 
-    let theme = match theme_str.as_str() {
-        "dark" => Theme::Dark,
-        "light" => Theme::Light,
-        "system" => Theme::System,
-        _ => Theme::System,
+    build_with_options(&mut cx, options)
+}
+
+pub fn build_from_template_with_config(mut cx: FunctionContext) -> JsResult<JsValue> {
+    if !(3..=4).contains(&cx.len()) {
+        return cx.throw_error("Invalid number of arguments");
+    }
+
+    let config = cx.argument::<JsObject>(2)?;
+    let trailing = TrailingOptions::read(&mut cx, 3)?;
+    let options = BuildOptions {
+        parent: parent_arg(&mut cx, 0)?,
+        template: cx.argument::<JsArray>(1)?.to_vec(&mut cx)?,
+        config: to_config(&mut cx, config, trailing.strict)?,
+        boxed: trailing.boxed,
     };
 
-    let config = Config {
-        theme,
-        ..Default::default()
+    build_with_options(&mut cx, options)
+}
+
+/// Builds a menu from `(parent, path, options?)`, where the options are those of buildMenu without the template,
+/// plus `format`, `watch` and `onReload`. The format is told from the extension unless given.
+///
+/// With `watch`, the menu is rebuilt under the same handle whenever the file changes, and `onReload` is called
+/// with `{ type: "reloaded" }` or `{ type: "reloadFailed", error }`.
+pub fn build_from_file(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let parent = parent_arg(&mut cx, 0)?;
+    let path = cx.argument::<JsString>(1)?.value(&mut cx);
+    let options = options_arg(&mut cx, 2)?;
+
+    let format = match options.get_opt::<JsString, _, _>(&mut cx, "format")? {
+        Some(format) => Some(Format::from_name(&format.value(&mut cx)).or_throw(&mut cx)?),
+        None => None,
+    };
+    let source = FileSource {
+        path: PathBuf::from(path),
+        format,
+        style: StyleOptions::read(&mut cx, &options)?,
     };
+    let (items, config) = source.load().or_throw(&mut cx)?;
+    let boxed = to_boxed(&mut cx, &options)?;
+
+    let (state, submenus) = build_native(parent, &items, config).or_throw(&mut cx)?;
+    let menu_id = registry::register(state, submenus);
+
+    let watch = options.get_opt::<JsBoolean, _, _>(&mut cx, "watch")?.is_some_and(|watch| watch.value(&mut cx));
+    if watch {
+        let listener = options.get_opt::<JsFunction, _, _>(&mut cx, "onReload")?.map(|listener| Arc::new(listener.root(&mut cx)));
+        let mut channel = cx.channel();
+        // Watching alone does not keep the process alive
+        channel.unref(&mut cx);
+
+        let path = source.path.clone();
+        let source = Arc::new(source);
+        let watch = Watch::start(path, move || {
+            let source = source.clone();
+            let listener = listener.clone();
+            channel.send(move |mut cx| reload_file(&mut cx, menu_id, &source, listener));
+        });
+        registry::write(menu_id as f64, |state| state.watch = Some(watch)).or_throw(&mut cx)?;
+    }
 
-    let menu_handle = build(&mut cx, parent, templates, config);
-    let id = cx.number(menu_handle as i32);
-    Ok(id)
+    to_menu_handle(&mut cx, menu_id, boxed)
 }
 
-pub fn build_from_template_with_config(mut cx: FunctionContext) -> JsResult<JsNumber> {
-    if cx.len() != 3 {
-        return cx.throw_error("Invalid number of arguments");
+/// Rebuilds a watched menu from its file and reports the outcome. Nothing is reported once the menu is destroyed.
+fn reload_file<'a, C: Context<'a>>(cx: &mut C, menu_id: u32, source: &FileSource, listener: Option<Arc<Root<JsFunction>>>) -> NeonResult<()> {
+    let menu_handle = menu_id as f64;
+    let result = source.load().and_then(|(items, config)| {
+        let parent = registry::read(menu_handle, |state| state.parent)?;
+        let (state, submenus) = build_native(parent, &items, config)?;
+        match registry::replace(menu_handle, state, submenus) {
+            Ok(previous) => {
+                teardown(&previous);
+                Ok(())
+            }
+            Err((e, rebuilt)) => {
+                teardown(&rebuilt);
+                Err(e)
+            }
+        }
+    });
+
+    let Some(listener) = listener else {
+        return Ok(());
+    };
+    let event = cx.empty_object();
+    match result {
+        Ok(()) => {
+            let event_type = cx.string("reloaded");
+            event.set(cx, "type", event_type)?;
+        }
+        Err(MenuError::UnknownHandle(_)) => return Ok(()),
+        Err(e) => {
+            let event_type = cx.string("reloadFailed");
+            event.set(cx, "type", event_type)?;
+            let error = e.to_js_error(cx)?;
+            event.set(cx, "error", error)?;
+        }
     }
 
-    let parent = cx.argument::<JsNumber>(0)?.value(&mut cx);
-    let templates = cx.argument::<JsArray>(1)?.to_vec(&mut cx).unwrap();
-    let config_obj = cx.argument::<JsObject>(2)?;
+    let listener = listener.to_inner(cx);
+    listener.call_with(cx).arg(event).exec(cx)
+}
 
-    let config = to_config(&mut cx, config_obj);
+fn build_with_options<'a>(cx: &mut FunctionContext<'a>, options: BuildOptions<'a>) -> JsResult<'a, JsValue> {
+    let menu_id = build(cx, options.parent, options.template, options.config)?;
+    to_menu_handle(cx, menu_id, options.boxed)
+}
 
-    let menu_handle = build(&mut cx, parent, templates, config);
+fn to_menu_handle<'a>(cx: &mut FunctionContext<'a>, menu_id: u32, boxed: bool) -> JsResult<'a, JsValue> {
+    let menu_handle = menu_id as f64;
+    if boxed {
+        Ok(cx.boxed(MenuBox::new(menu_handle)).upcast())
+    } else {
+        Ok(cx.number(menu_handle).upcast())
+    }
+}
 
-    let id = cx.number(menu_handle as i32);
-    Ok(id)
+fn build(cx: &mut FunctionContext, parent: u64, templates: Vec<Handle<JsValue>>, config: Config) -> NeonResult<u32> {
+    let items = read_template(cx, templates)?;
+    let (state, submenus) = build_native(parent, &items, config).or_throw(cx)?;
+    Ok(registry::register(state, submenus))
 }
 
-fn build(cx: &mut FunctionContext, parent: f64, templates: Vec<Handle<JsValue>>, config: Config) -> isize {
-    let items: Vec<ElectronMenuItem> = templates
-        .into_iter()
-        .map(|value| {
-            let v = value.downcast_or_throw::<JsObject, _>(cx).unwrap();
-            ElectronMenuItem::from_object(cx, v)
-        })
-        .collect();
+/// Creates the native menu and its submenus without registering them.
+fn build_native(parent: u64, items: &Vec<ElectronMenuItem>, mut config: Config) -> Result<(MenuState, Vec<Menu>), MenuError> {
+    let requested_theme = config.theme;
 
     #[cfg(target_os = "linux")]
     {
-        let _ = gtk::init();
+        gtk::init().map_err(|e| MenuError::BackendFailure(e.to_string()))?;
+        theme::watch();
+        config.theme = theme::native_theme(requested_theme);
         let display = unsafe { gdk_x11_get_default_xdisplay() };
 
         let x11_display = unsafe { gdk_x11_lookup_xdisplay(display) };
-        let gdkwindow = unsafe { gdk_x11_window_foreign_new_for_display(x11_display, parent as u64) };
+        let gdkwindow = unsafe { gdk_x11_window_foreign_new_for_display(x11_display, parent) };
+        if gdkwindow.is_null() {
+            return Err(MenuError::BackendFailure(format!("Window {} does not exist", parent)));
+        }
         let gdk_window: Window = unsafe { Window::from_glib_full(gdkwindow) };
         let window = gtk::Window::new(gtk::WindowType::Toplevel);
         window.connect_realize(glib::clone!(@weak gdk_window as wd => move |w| w.set_window(wd)));
@@ -103,39 +230,54 @@ fn build(cx: &mut FunctionContext, parent: f64, templates: Vec<Handle<JsValue>>,
         window.realize();
 
         let mut builder = MenuBuilder::new_for_window_from_config(&window, config);
-        build_menu(&mut builder, &items);
-        let menu = builder.build().unwrap();
-        let mut map = MENU_MAP.try_lock().unwrap();
+        let mut submenus = Vec::new();
+        build_menu(&mut builder, items, &mut submenus)?;
+        let menu = builder.build().map_err(|e| MenuError::BackendFailure(e.to_string()))?;
 
-        let menu_handle = menu.gtk_menu_handle;
-        (*map).insert(menu_handle as i32, menu);
+        let window_ptr: *mut gtk::ffi::GtkWindow = window.to_glib_none().0;
+        let mut state = MenuState::new(menu, Arc::<Popup>::default());
+        state.window_handle = window_ptr as isize;
+        state.parent = parent;
+        state.theme = requested_theme;
 
-        menu_handle
+        Ok((state, submenus))
     }
 
     #[cfg(target_os = "windows")]
     {
-        let mut builder = MenuBuilder::new_from_config(parent as isize, config);
+        theme::watch();
+        config.theme = theme::native_theme(requested_theme);
+        let mut builder = MenuBuilder::new_from_config(parent as isize, map_border_colors(config, color::to_colorref));
 
-        build_menu(&mut builder, &items);
-        let menu = builder.build().unwrap();
+        let mut submenus = Vec::new();
+        build_menu(&mut builder, items, &mut submenus)?;
+        let menu = builder.build().map_err(|e| MenuError::BackendFailure(e.to_string()))?;
 
-        let mut map = MENU_MAP.try_lock().unwrap();
+        let popup = Arc::<Popup>::default();
+        outcome::track(menu.window_handle, popup.clone());
 
-        let menu_handle = menu.window_handle;
-        (*map).insert(menu_handle as i32, menu);
+        let mut state = MenuState::new(menu, popup);
+        state.parent = parent;
+        state.theme = requested_theme;
 
-        menu_handle
+        Ok((state, submenus))
     }
 }
 
-fn build_menu(builder: &mut MenuBuilder, items: &Vec<ElectronMenuItem>) {
+/// wcpopup hands the border color to DWM as a `COLORREF` when the menu has a border, but draws its other
+/// colors, and separators in the border color, from the native layout.
+#[cfg(target_os = "windows")]
+fn map_border_colors(mut config: Config, map: fn(u32) -> u32) -> Config {
+    if config.size.border_size > 0 {
+        config.color.dark.border = map(config.color.dark.border);
+        config.color.light.border = map(config.color.light.border);
+    }
+    config
+}
+
+fn build_menu(builder: &mut MenuBuilder, items: &Vec<ElectronMenuItem>, submenus: &mut Vec<Menu>) -> Result<(), MenuError> {
     for item in items {
-        let disabled = if item.enabled {
-            None
-        } else {
-            Some(false)
-        };
+        let disabled = item.disabled();
         match item.itype.as_str() {
             "normal" => {
                 if item.accelerator.is_empty() {
@@ -149,14 +291,9 @@ fn build_menu(builder: &mut MenuBuilder, items: &Vec<ElectronMenuItem>) {
             }
             "submenu" => {
                 let mut parent = builder.submenu(&item.id, &item.label, disabled);
-                build_menu(&mut parent, &item.submenu);
-                let submenu = parent.build().unwrap();
-                let mut map = MENU_MAP.try_lock().unwrap();
-                #[cfg(target_os = "linux")]
-                (*map).insert(submenu.gtk_menu_handle as i32, submenu);
-                #[cfg(target_os = "windows")]
-                (*map).insert(submenu.window_handle as i32, submenu);
-                std::mem::drop(map);
+                build_menu(&mut parent, &item.submenu, submenus)?;
+                let submenu = parent.build().map_err(|e| MenuError::BackendFailure(e.to_string()))?;
+                submenus.push(submenu);
             }
             "checkbox" => {
                 if item.accelerator.is_empty() {
@@ -175,6 +312,8 @@ fn build_menu(builder: &mut MenuBuilder, items: &Vec<ElectronMenuItem>) {
             _ => {}
         }
     }
+
+    Ok(())
 }
 
 pub fn popup(mut cx: FunctionContext) -> JsResult<JsPromise> {
@@ -182,148 +321,621 @@ pub fn popup(mut cx: FunctionContext) -> JsResult<JsPromise> {
         return cx.throw_error("Invalid number of arguments");
     }
 
-    let menu_handle = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
     let x = cx.argument::<JsNumber>(1)?.value(&mut cx);
     let y = cx.argument::<JsNumber>(2)?.value(&mut cx);
 
+    // Nothing stays locked while the menu is open, so other menus can be used and this one can be queried
+    let shared = registry::get(menu_handle).or_throw(&mut cx)?;
+    let (menu, popup) = {
+        let state = registry::lock(&shared);
+        if !state.popup.open(&state.menu) {
+            return MenuError::MenuBusy.throw(&mut cx);
+        }
+        (state.menu.clone(), state.popup.clone())
+    };
+
     let (deferred, promise) = cx.promise();
     let channel = cx.channel();
 
     #[cfg(target_os = "windows")]
     async_std::task::spawn(async move {
-        let map = MENU_MAP.lock().await;
-        let menu = map.get(&(menu_handle as i32)).unwrap();
-        let x = menu.popup_at_async(x as i32, y as i32).await;
+        let item = menu.popup_at_async(x as i32, y as i32).await;
+        let outcome = finish_popup(&menu, &popup, item);
+        let alive = registry::contains(menu_handle, &shared);
 
-        deferred.settle_with(&channel, |mut cx| match x {
-            Some(data) => from_menu_item(&mut cx, &data),
-            None => Ok(cx.empty_object()),
+        deferred.settle_with(&channel, move |mut cx| {
+            if !alive {
+                return MenuError::UnknownHandle(menu_handle).throw(&mut cx);
+            }
+            from_outcome(&mut cx, &outcome)
         });
     });
     #[cfg(target_os = "linux")]
-    gtk::glib::spawn_future_local(async move {
-        let map = MENU_MAP.lock().await;
-        let menu = map.get(&(menu_handle as i32)).unwrap();
-        let x = menu.popup_at_async(x as i32, y as i32).await;
-        deferred.settle_with(&channel, |mut cx| match x {
-            Some(data) => from_menu_item(&mut cx, &data),
-            None => Ok(cx.empty_object()),
+    {
+        let tracker = outcome::Tracker::start(&menu, popup.clone());
+        gtk::glib::spawn_future_local(async move {
+            let item = menu.popup_at_async(x as i32, y as i32).await;
+            tracker.stop();
+            let outcome = finish_popup(&menu, &popup, item);
+            let alive = registry::contains(menu_handle, &shared);
+
+            deferred.settle_with(&channel, move |mut cx| {
+                if !alive {
+                    return MenuError::UnknownHandle(menu_handle).throw(&mut cx);
+                }
+                from_outcome(&mut cx, &outcome)
+            });
         });
-    });
+    }
 
     Ok(promise)
 }
 
+/// Marks the menu as closed and describes how it closed.
+fn finish_popup(menu: &Menu, popup: &Popup, item: Option<MenuItem>) -> Outcome {
+    let (input, cancel_reason) = popup.finish();
+    Outcome::new(menu, item, input, cancel_reason)
+}
+
+pub fn close(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let reason = match cx.argument_opt(1) {
+        Some(reason) if !reason.is_a::<JsUndefined, _>(&mut cx) => reason.downcast_or_throw::<JsString, _>(&mut cx)?.value(&mut cx),
+        _ => String::from("closed"),
+    };
+
+    let shown = registry::read(menu_handle, |state| state.popup.cancel(&reason)).or_throw(&mut cx)?;
+    if let Some(menu) = &shown {
+        close_native(menu);
+    }
+
+    Ok(cx.boolean(shown.is_some()))
+}
+
+/// Dismisses a menu that is showing. The pending popup resolves once the menu is hidden.
+fn close_native(menu: &Menu) {
+    #[cfg(target_os = "linux")]
+    {
+        let gtk_menu: gtk::Menu = unsafe { gtk::Menu::from_glib_none(menu.gtk_menu_handle as *mut gtk::ffi::GtkMenu) };
+        gtk_menu.popdown();
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // Escape lets wcpopup release its hooks and resolve the pending popup
+        let hwnd = HWND(menu.window_handle as _);
+        unsafe { SendMessageW(hwnd, WM_KEYDOWN, WPARAM(VK_ESCAPE.0 as usize), LPARAM(0)) };
+    }
+}
+
 pub fn items(mut cx: FunctionContext) -> JsResult<JsArray> {
-    let menu_handle = cx.argument::<JsNumber>(0)?.value(&mut cx);
-    let map = MENU_MAP.try_lock().unwrap();
-    let menu = map.get(&(menu_handle as i32)).unwrap();
-    let items = extract_item(&menu.items(), &mut cx)?;
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let depth = depth_option(&mut cx, 1)?;
+    let items = registry::read(menu_handle, |state| state.menu.items()).or_throw(&mut cx)?;
+    let items = extract_item(&items, depth, &mut cx)?;
 
     Ok(items)
 }
 
+/// Returns the items of a menu and its submenus as a template that builds the same menu again.
+pub fn to_template(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let items = registry::read(menu_handle, |state| ElectronMenuItem::from_menu_items(&state.menu.items())).or_throw(&mut cx)?;
+    from_template(&mut cx, &items)
+}
+
 pub fn remove(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-    let menu_handle = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
     let jsitem = cx.argument::<JsObject>(1)?;
 
-    let item = to_menu_item(&mut cx, jsitem);
+    let item = to_menu_item(&mut cx, jsitem)?;
 
-    let mut map = MENU_MAP.try_lock().unwrap();
-    let menu = map.get_mut(&(menu_handle as i32)).unwrap();
-    menu.remove(&item);
+    registry::write(menu_handle, |state| state.menu.remove(&item)).or_throw(&mut cx)?;
 
     Ok(cx.undefined())
 }
 
 pub fn remove_at(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-    let menu_handle = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
     let index = cx.argument::<JsNumber>(1)?.value(&mut cx);
 
-    let mut map = MENU_MAP.try_lock().unwrap();
-    let menu = map.get_mut(&(menu_handle as i32)).unwrap();
-    menu.remove_at(index as u32);
+    registry::write(menu_handle, |state| state.menu.remove_at(index as u32)).or_throw(&mut cx)?;
 
     Ok(cx.undefined())
 }
 
-pub fn append(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-    let menu_handle = cx.argument::<JsNumber>(0)?.value(&mut cx);
+/// Adds an item at the end. A submenu item is built with its items, and the handle of its submenu is returned.
+pub fn append(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
     let jsitem = cx.argument::<JsObject>(1)?;
 
-    let mut map = MENU_MAP.try_lock().unwrap();
-    let menu = map.get_mut(&(menu_handle as i32)).unwrap();
-    let item = to_menu_item(&mut cx, jsitem);
-    menu.append(item);
-
-    Ok(cx.undefined())
+    let item = to_new_item(&mut cx, jsitem)?;
+    apply_operation(&mut cx, menu_handle, Operation::Append(item))
 }
 
-pub fn insert(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-    let menu_handle = cx.argument::<JsNumber>(0)?.value(&mut cx);
+/// Like append, at the index.
+pub fn insert(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
     let index = cx.argument::<JsNumber>(1)?.value(&mut cx);
     let jsitem = cx.argument::<JsObject>(2)?;
 
-    let mut map = MENU_MAP.try_lock().unwrap();
-    let menu = map.get_mut(&(menu_handle as i32)).unwrap();
-    let item = to_menu_item(&mut cx, jsitem);
-    menu.insert(item, index as u32);
+    let item = to_new_item(&mut cx, jsitem)?;
+    apply_operation(&mut cx, menu_handle, Operation::Insert(index as usize, item))
+}
+
+/// Changes the fields of the item with the id, which may be in a submenu, and returns the updated item.
+pub fn update_item(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let id = cx.argument::<JsString>(1)?.value(&mut cx);
+    let patch = cx.argument::<JsObject>(2)?;
+    let mut diagnostics = Vec::new();
+    let patch = ItemPatch::read(&mut cx, patch, "patch", &mut diagnostics)?;
+    if !diagnostics.is_empty() {
+        return MenuError::InvalidTemplate(diagnostics).throw(&mut cx);
+    }
+
+    apply_operation(&mut cx, menu_handle, Operation::Update(id, patch))
+}
+
+/// Removes the item with the id, which may be in a submenu.
+pub fn remove_by_id(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let id = cx.argument::<JsString>(1)?.value(&mut cx);
+
+    apply_operation(&mut cx, menu_handle, Operation::RemoveById(id))
+}
+
+/// Inserts an item before the item with the id, in the menu holding it, and returns the handle of a new submenu as append does.
+pub fn insert_before(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let id = cx.argument::<JsString>(1)?.value(&mut cx);
+    let jsitem = cx.argument::<JsObject>(2)?;
+
+    let item = to_new_item(&mut cx, jsitem)?;
+    apply_operation(&mut cx, menu_handle, Operation::InsertBefore(id, item))
+}
+
+/// Like insert_before, after the item.
+pub fn insert_after(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let id = cx.argument::<JsString>(1)?.value(&mut cx);
+    let jsitem = cx.argument::<JsObject>(2)?;
+
+    let item = to_new_item(&mut cx, jsitem)?;
+    apply_operation(&mut cx, menu_handle, Operation::InsertAfter(id, item))
+}
+
+/// Moves the item with the id into the submenu of the item with the target id, or into the menu itself if the target is null,
+/// so that it ends up at the index there.
+pub fn move_item(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let id = cx.argument::<JsString>(1)?.value(&mut cx);
+    let target = match cx.argument_opt(2) {
+        Some(value) if !is_nullish(&mut cx, value) => Some(value.downcast_or_throw::<JsString, _>(&mut cx)?.value(&mut cx)),
+        _ => None,
+    };
+    let index = cx.argument::<JsNumber>(3)?.value(&mut cx);
+
+    apply_operation(
+        &mut cx,
+        menu_handle,
+        Operation::Move {
+            id,
+            target,
+            index: index as usize,
+        },
+    )
+}
+
+fn apply_operation<'a>(cx: &mut FunctionContext<'a>, menu_handle: f64, operation: Operation) -> JsResult<'a, JsValue> {
+    let effect = registry::write(menu_handle, |state| operation.apply(&state.menu)).and_then(|result| result).or_throw(cx)?;
+    let output = effect.record(menu_handle).or_throw(cx)?;
+    from_output(cx, &output)
+}
+
+/// Changes the menu to match the template with as few changes as it takes, keeping the items whose ids are still
+/// there along with the handles of their submenus.
+pub fn set_template(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let template = cx.argument::<JsArray>(1)?.to_vec(&mut cx)?;
+    let template = read_template(&mut cx, template)?;
+    diff::check_ids(&template).or_throw(&mut cx)?;
+
+    let effects = registry::write(menu_handle, |state| {
+        let steps = diff::diff(ElectronMenuItem::from_menu_items(&state.menu.items()), &template);
+        edit::apply_steps(&state.menu, steps)
+    })
+    .and_then(|result| result)
+    .or_throw(&mut cx)?;
+    for effect in effects {
+        effect.record(menu_handle).or_throw(&mut cx)?;
+    }
 
     Ok(cx.undefined())
 }
 
+/// Applies the operations in order under one lock of the menu, and returns what each of them would return on its own.
+/// Nothing is changed if any operation is invalid.
+pub fn batch(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let operations = cx.argument::<JsArray>(1)?.to_vec(&mut cx)?;
+    let operations = to_operations(&mut cx, operations)?;
+
+    let effects = registry::write(menu_handle, |state| edit::apply_all(&state.menu, operations)).and_then(|result| result).or_throw(&mut cx)?;
+    let outputs = effects.into_iter().map(|effect| effect.record(menu_handle)).collect::<Result<Vec<_>, _>>().or_throw(&mut cx)?;
+
+    let results = cx.empty_array();
+    for (index, output) in outputs.iter().enumerate() {
+        let result = from_output(&mut cx, output)?;
+        results.set(&mut cx, index as u32, result)?;
+    }
+    Ok(results)
+}
+
 pub fn get_menu_item_by_id(mut cx: FunctionContext) -> JsResult<JsObject> {
-    let menu_handle = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
     let id = cx.argument::<JsString>(1)?.value(&mut cx);
 
-    let map = MENU_MAP.try_lock().unwrap();
-    let menu = map.get(&(menu_handle as i32)).unwrap();
-    if let Some(item) = menu.get_menu_item_by_id(id.as_str()) {
+    let item = registry::read(menu_handle, |state| state.menu.get_menu_item_by_id(id.as_str())).or_throw(&mut cx)?;
+    if let Some(item) = item {
         from_menu_item(&mut cx, &item)
     } else {
         Ok(cx.empty_object())
     }
 }
 
+pub fn destroy(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+
+    if registry::read(menu_handle, |state| state.menu.menu_type).or_throw(&mut cx)? == MenuType::Submenu {
+        return MenuError::InvalidArgument(format!("Menu {} is a submenu", menu_handle)).throw(&mut cx);
+    }
+
+    release(menu_handle);
+
+    if let Some(boxed) = menu_box_arg(&mut cx, 0) {
+        boxed.forget();
+    }
+
+    Ok(cx.undefined())
+}
+
+pub fn get_handle(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    Ok(cx.number(menu_handle))
+}
+
+/// Returns the GtkMenu pointer or HWND of the menu.
+pub fn get_native_handle(mut cx: FunctionContext) -> JsResult<JsBigInt> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let native_handle = registry::read(menu_handle, |state| registry::native_handle(&state.menu)).or_throw(&mut cx)?;
+    Ok(JsBigInt::from_i64(&mut cx, native_handle as i64))
+}
+
+/// Destroys a boxed menu that was garbage collected.
+pub(crate) fn release(menu_handle: f64) {
+    if let Some(shared) = registry::remove(menu_handle) {
+        let mut state = registry::lock(&shared);
+        teardown(&state);
+        // A popup may still hold the state, so the watch is stopped here rather than when the state is dropped
+        state.watch = None;
+    }
+}
+
+/// Closes the menu if open and releases its native resources.
+fn teardown(state: &MenuState) {
+    if let Some(menu) = state.popup.cancel("destroyed") {
+        close_native(&menu);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let gtk_menu: gtk::Menu = unsafe { gtk::Menu::from_glib_none(state.menu.gtk_menu_handle as *mut gtk::ffi::GtkMenu) };
+
+        destroy_submenus(&gtk_menu);
+        for child in gtk_menu.children() {
+            gtk_menu.remove(&child);
+        }
+
+        // The main gtk::Menu itself is kept alive because wcpopup connects GtkSettings handlers
+        // that refer to it and are never disconnected.
+        gtk_menu.detach();
+
+        let window: gtk::Window = unsafe { gtk::Window::from_glib_none(state.window_handle as *mut gtk::ffi::GtkWindow) };
+        // The GdkWindow of the widget wraps the parent window, which belongs to the application. Destroying the
+        // widget unrealizes it, and GDK destroys a foreign window by hiding it, reparenting it to the root window
+        // and asking it to close, as a window manager would. So the widget is given a window of its own to destroy.
+        let placeholder = gdk::Window::new(
+            None,
+            &gdk::WindowAttr {
+                window_type: gdk::WindowType::Toplevel,
+                ..Default::default()
+            },
+        );
+        window.set_window(placeholder);
+        unsafe { window.destroy() };
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let hwnd = HWND(state.menu.window_handle as _);
+        // Submenu windows are owned by the main menu window and are destroyed along with it
+        let _ = unsafe { DestroyWindow(hwnd) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn destroy_submenus(gtk_menu: &gtk::Menu) {
+    for child in gtk_menu.children() {
+        if let Some(submenu) = child.downcast_ref::<gtk::MenuItem>().and_then(|item| item.submenu()) {
+            if let Some(submenu) = submenu.downcast_ref::<gtk::Menu>() {
+                destroy_submenus(submenu);
+            }
+            unsafe { submenu.destroy() };
+        }
+    }
+}
+
+pub fn validate(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let template = cx.argument::<JsValue>(0)?;
+    let diagnostics = validate_template(&mut cx, template)?;
+    from_diagnostics(&mut cx, &diagnostics)
+}
+
+pub fn check_config(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let config = cx.argument::<JsValue>(0)?;
+    let diagnostics = validate_config(&mut cx, config)?;
+    from_diagnostics(&mut cx, &diagnostics)
+}
+
+/// Applies a partial config to a main menu and its submenus.
+///
+/// wcpopup cannot restyle a built menu, so the menu is built again from its current items and
+/// swapped in under the same ids. Handles to the menu and its submenus stay valid,
+/// and items returned before can still be removed.
+pub fn set_config(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let partial = cx.argument::<JsObject>(1)?;
+    let strict = TrailingOptions::read(&mut cx, 2)?.strict;
+
+    ensure_main_menu(&mut cx, menu_handle)?;
+    let mut config = registry::read(menu_handle, effective_config).or_throw(&mut cx)?;
+    // The config is read before the menu is locked, since its getters may call back into this module
+    merge_config(&mut cx, partial, &mut config, strict)?;
+
+    apply_config(&mut cx, menu_handle, config)?;
+
+    Ok(cx.undefined())
+}
+
+fn ensure_main_menu(cx: &mut FunctionContext, menu_handle: f64) -> NeonResult<()> {
+    let menu_type = registry::read(menu_handle, |state| state.menu.menu_type).or_throw(cx)?;
+    if menu_type == MenuType::Submenu {
+        return MenuError::InvalidArgument(format!("Menu {} is a submenu", menu_handle)).throw(cx);
+    }
+    Ok(())
+}
+
+/// Rebuilds a main menu with the config and swaps it in under the same handle.
+fn apply_config(cx: &mut FunctionContext, menu_handle: f64, config: Config) -> NeonResult<()> {
+    let (parent, items) = registry::write(menu_handle, |state| (state.parent, ElectronMenuItem::from_menu_items(&state.menu.items()))).or_throw(cx)?;
+    let (state, submenus) = rebuild(parent, &items, config).or_throw(cx)?;
+
+    match registry::replace(menu_handle, state, submenus) {
+        Ok(previous) => {
+            teardown(&previous);
+            Ok(())
+        }
+        Err((e, rebuilt)) => {
+            teardown(&rebuilt);
+            e.throw(cx)
+        }
+    }
+}
+
+/// Builds a menu again from the items of a built one. Its items keep their uuids, so that items returned
+/// before are still found by remove.
+fn rebuild(parent: u64, items: &Vec<ElectronMenuItem>, config: Config) -> Result<(MenuState, Vec<Menu>), MenuError> {
+    let (state, _) = build_native(parent, items, config)?;
+    let submenus = edit::restore_uuids(&state.menu, items);
+    Ok((state, submenus))
+}
+
+/// Returns the config a menu was built with, including any later setConfig and setTheme changes.
+pub fn get_config(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let menu_handle = menu_handle_arg(&mut cx, 0)?;
+    let hex_colors = hex_colors_option(&mut cx, 1)?;
+
+    let config = registry::read(menu_handle, effective_config).or_throw(&mut cx)?;
+    from_config(&mut cx, &config, hex_colors)
+}
+
+/// Reports the theme the app asked for rather than the one it was resolved to.
+fn effective_config(state: &MenuState) -> Config {
+    let config = Config {
+        theme: state.theme,
+        ..state.menu.config()
+    };
+    #[cfg(target_os = "windows")]
+    let config = map_border_colors(config, color::from_colorref);
+    config
+}
+
 pub fn get_default_config(mut cx: FunctionContext) -> JsResult<JsObject> {
-    let configjs = from_config(&mut cx, &Config::default())?;
+    let hex_colors = hex_colors_option(&mut cx, 0)?;
+    let configjs = from_config(&mut cx, &Config::default(), hex_colors)?;
     Ok(configjs)
 }
 
 pub fn set_theme(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-    let id = cx.argument::<JsNumber>(0)?.value(&mut cx);
-    let theme_str = cx.argument::<JsString>(1)?.value(&mut cx);
+    let id = menu_handle_arg(&mut cx, 0)?;
+    let theme = cx.argument::<JsString>(1)?.value(&mut cx);
+
+    match theme::to_preset(&theme) {
+        Preset::Theme(theme) => registry::set_theme(id, theme).or_throw(&mut cx)?,
+        // A preset changes more than the theme, so the menu is rebuilt as setConfig does
+        Preset::Config(config) => {
+            ensure_main_menu(&mut cx, id)?;
+            apply_config(&mut cx, id, config)?;
+        }
+    }
 
-    let theme = match theme_str.as_str() {
-        "dark" => Theme::Dark,
-        "light" => Theme::Light,
-        "system" => Theme::System,
-        _ => Theme::System,
-    };
+    Ok(cx.undefined())
+}
+
+/// Registers a named preset that buildFromTemplateWithTheme, buildMenu and setTheme accept in place of a theme.
+/// The config is merged over the default config.
+pub fn register_theme(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let name = cx.argument::<JsString>(0)?.value(&mut cx);
+    let partial = cx.argument::<JsObject>(1)?;
+    let strict = TrailingOptions::read(&mut cx, 2)?.strict;
 
-    let mut map = MENU_MAP.try_lock().unwrap();
-    let menu = map.get_mut(&(id as i32)).unwrap();
-    menu.set_theme(theme);
+    let config = to_config(&mut cx, partial, strict)?;
+    theme::register_preset(name, config).or_throw(&mut cx)?;
 
     Ok(cx.undefined())
 }
 
+pub fn list_themes(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let names = theme::preset_names();
+    let array = JsArray::new(&mut cx, names.len());
+    for (index, name) in names.iter().enumerate() {
+        let name = cx.string(name);
+        array.set(&mut cx, index as u32, name)?;
+    }
+    Ok(array)
+}
+
+/// Calls back with "dark" or "light" whenever the desktop theme changes. Returns an id for offSystemThemeChanged.
+pub fn on_system_theme_changed(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let callback = cx.argument::<JsFunction>(0)?.root(&mut cx);
+
+    #[cfg(target_os = "linux")]
+    gtk::init().map_err(|e| MenuError::BackendFailure(e.to_string())).or_throw(&mut cx)?;
+    theme::watch();
+
+    let mut channel = cx.channel();
+    // Listening alone does not keep the process alive
+    channel.unref(&mut cx);
+
+    let id = theme::subscribe(callback, channel);
+    Ok(cx.number(id))
+}
+
+pub fn off_system_theme_changed(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let removed = theme::unsubscribe(id as u32);
+    Ok(cx.boolean(removed))
+}
+
+pub fn get_system_theme(mut cx: FunctionContext) -> JsResult<JsString> {
+    #[cfg(target_os = "linux")]
+    gtk::init().map_err(|e| MenuError::BackendFailure(e.to_string())).or_throw(&mut cx)?;
+
+    Ok(cx.string(theme::theme_name(theme::system_theme())))
+}
+
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
+    cx.export_function("buildMenu", build_menu_from_options)?;
     cx.export_function("buildFromTemplate", build_from_template)?;
     cx.export_function("buildFromTemplateWithTheme", build_from_template_with_theme)?;
     cx.export_function("buildFromTemplateWithConfig", build_from_template_with_config)?;
+    cx.export_function("buildFromFile", build_from_file)?;
 
     cx.export_function("setTheme", set_theme)?;
+    cx.export_function("registerTheme", register_theme)?;
+    cx.export_function("listThemes", list_themes)?;
+    cx.export_function("setConfig", set_config)?;
+    cx.export_function("getConfig", get_config)?;
     cx.export_function("items", items)?;
+    cx.export_function("toTemplate", to_template)?;
     cx.export_function("removeAt", remove_at)?;
     cx.export_function("remove", remove)?;
     cx.export_function("append", append)?;
     cx.export_function("insert", insert)?;
     cx.export_function("getMenuItemById", get_menu_item_by_id)?;
+    cx.export_function("updateItem", update_item)?;
+    cx.export_function("removeById", remove_by_id)?;
+    cx.export_function("insertBefore", insert_before)?;
+    cx.export_function("insertAfter", insert_after)?;
+    cx.export_function("moveItem", move_item)?;
+    cx.export_function("batch", batch)?;
+    cx.export_function("setTemplate", set_template)?;
     cx.export_function("popup", popup)?;
+    cx.export_function("close", close)?;
+    cx.export_function("destroy", destroy)?;
+    cx.export_function("getHandle", get_handle)?;
+    cx.export_function("getNativeHandle", get_native_handle)?;
 
     cx.export_function("getDefaultConfig", get_default_config)?;
+    cx.export_function("getSystemTheme", get_system_theme)?;
+    cx.export_function("onSystemThemeChanged", on_system_theme_changed)?;
+    cx.export_function("offSystemThemeChanged", off_system_theme_changed)?;
+    cx.export_function("validateTemplate", validate)?;
+    cx.export_function("validateConfig", check_config)?;
 
     Ok(())
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// A shown window to build menus for, with its X11 id.
+    fn parent_window() -> (gtk::Window, u64) {
+        gtk::init().unwrap();
+        let parent = gtk::Window::new(gtk::WindowType::Toplevel);
+        parent.show();
+        let xid = parent.window().unwrap().downcast_ref::<gdkx11::X11Window>().unwrap().xid();
+        (parent, xid)
+    }
+
+    #[test]
+    #[ignore = "needs an X display"]
+    fn destroying_a_menu_keeps_the_parent_window() {
+        let (parent, xid) = parent_window();
+        let parent_window = parent.window().unwrap();
+
+        let items = vec![];
+        let (state, _) = build_native(xid, &items, Config::default()).unwrap();
+        teardown(&state);
+        while gtk::events_pending() {
+            gtk::main_iteration();
+        }
+
+        // GDK hands out the same GdkWindow for the parent, so destroying it would show here
+        assert!(!parent_window.is_destroyed());
+        assert!(parent.is_visible());
+    }
+
+    #[test]
+    #[ignore = "needs an X display"]
+    fn items_returned_before_a_rebuild_are_still_found() {
+        let (_parent, xid) = parent_window();
+        let node = document::parse_json(r#"[{"id": "a"}, {"id": "s", "submenu": [{"id": "b", "accelerator": "Ctrl+B"}, {"id": "t", "submenu": [{"id": "c"}]}]}]"#).unwrap();
+        let document::Value::Array(nodes) = node.value else {
+            panic!("expected an array");
+        };
+        let mut diagnostics = Vec::new();
+        let template = ElectronMenuItem::from_nodes(&nodes, "template", &mut diagnostics);
+        let (built, _) = build_native(xid, &template, Config::default()).unwrap();
+
+        let items = ElectronMenuItem::from_menu_items(&built.menu.items());
+        let (mut rebuilt, submenus) = rebuild(xid, &items, Config::default()).unwrap();
+        assert_eq!(submenus.len(), 2);
+
+        fn uuids(items: &[ElectronMenuItem]) -> Vec<Option<u16>> {
+            items.iter().flat_map(|item| std::iter::once(item.uuid).chain(uuids(&item.submenu))).collect()
+        }
+        assert_eq!(uuids(&ElectronMenuItem::from_menu_items(&rebuilt.menu.items())), uuids(&items));
+
+        let returned = built.menu.items()[0].clone();
+        rebuilt.menu.remove(&returned);
+        assert_eq!(rebuilt.menu.items().iter().map(|item| item.id.clone()).collect::<Vec<_>>(), ["s"]);
+
+        teardown(&built);
+        teardown(&rebuilt);
+    }
+}
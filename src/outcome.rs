@@ -0,0 +1,246 @@
+use crate::registry::Popup;
+#[cfg(target_os = "linux")]
+use gtk::{
+    gdk::{self, ModifierType},
+    glib::{self, translate::FromGlibPtrNone, Cast, SignalHandlerId},
+    prelude::{ContainerExt, GtkMenuItemExt, ObjectExt, WidgetExt},
+};
+use std::sync::Arc;
+use wcpopup::{Menu, MenuItem};
+#[cfg(target_os = "windows")]
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        UI::{
+            Input::KeyboardAndMouse::{GetAsyncKeyState, VIRTUAL_KEY, VK_CONTROL, VK_ESCAPE, VK_LWIN, VK_MENU, VK_RWIN, VK_SHIFT},
+            Shell::{DefSubclassProc, RemoveWindowSubclass, SetWindowSubclass},
+            WindowsAndMessaging::{WM_KEYDOWN, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_NCDESTROY, WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SETTINGCHANGE},
+        },
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CloseReason {
+    /// An item was chosen.
+    Selected,
+    /// The menu was dismissed with the Escape key.
+    Escape,
+    /// The menu was dismissed by clicking outside of it or by losing focus, as to the Start menu.
+    Outside,
+    /// The menu was closed by close(), an AbortSignal or destroy().
+    Cancelled,
+}
+
+impl CloseReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloseReason::Selected => "selected",
+            CloseReason::Escape => "escape",
+            CloseReason::Outside => "outside",
+            CloseReason::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputSource {
+    Mouse,
+    Keyboard,
+}
+
+impl InputSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InputSource::Mouse => "mouse",
+            InputSource::Keyboard => "keyboard",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+/// The last input received while a popup was showing.
+#[derive(Debug, Clone, Copy)]
+pub struct Input {
+    pub source: InputSource,
+    pub modifiers: Modifiers,
+    pub escape: bool,
+}
+
+/// How a popup closed.
+pub struct Outcome {
+    pub reason: CloseReason,
+    pub item: Option<MenuItem>,
+    /// Ids from the root menu down to the chosen item.
+    pub path: Vec<String>,
+    pub input: Option<Input>,
+    pub cancel_reason: Option<String>,
+}
+
+impl Outcome {
+    pub fn new(menu: &Menu, item: Option<MenuItem>, input: Option<Input>, cancel_reason: Option<String>) -> Self {
+        let reason = match (&item, &cancel_reason, input) {
+            (Some(_), _, _) => CloseReason::Selected,
+            (None, Some(_), _) => CloseReason::Cancelled,
+            (None, None, Some(input)) if input.escape => CloseReason::Escape,
+            (None, None, _) => CloseReason::Outside,
+        };
+
+        let mut path = Vec::new();
+        if let Some(item) = &item {
+            if !find_path(menu.items(), item.uuid, &mut path) {
+                path = vec![item.id.clone()];
+            }
+        }
+
+        Self {
+            reason,
+            item,
+            path,
+            input,
+            cancel_reason,
+        }
+    }
+}
+
+fn find_path(items: Vec<MenuItem>, uuid: u16, path: &mut Vec<String>) -> bool {
+    for item in items {
+        path.push(item.id.clone());
+        if item.uuid == uuid {
+            return true;
+        }
+        if let Some(submenu) = &item.submenu {
+            if find_path(submenu.items(), uuid, path) {
+                return true;
+            }
+        }
+        path.pop();
+    }
+    false
+}
+
+/// Records input on the GTK menus of a popup until stopped.
+#[cfg(target_os = "linux")]
+pub struct Tracker {
+    handlers: Vec<(gtk::Menu, SignalHandlerId)>,
+}
+
+#[cfg(target_os = "linux")]
+impl Tracker {
+    pub fn start(menu: &Menu, popup: Arc<Popup>) -> Self {
+        let gtk_menu: gtk::Menu = unsafe { gtk::Menu::from_glib_none(menu.gtk_menu_handle as *mut gtk::ffi::GtkMenu) };
+        let mut tracker = Self {
+            handlers: Vec::new(),
+        };
+        tracker.connect(&gtk_menu, &popup);
+        tracker
+    }
+
+    fn connect(&mut self, gtk_menu: &gtk::Menu, popup: &Arc<Popup>) {
+        let key_popup = popup.clone();
+        let key = gtk_menu.connect_key_press_event(move |_, event| {
+            key_popup.record(Input {
+                source: InputSource::Keyboard,
+                modifiers: to_modifiers(event.state()),
+                escape: event.keyval() == gdk::keys::constants::Escape,
+            });
+            glib::Propagation::Proceed
+        });
+        self.handlers.push((gtk_menu.clone(), key));
+
+        let button_popup = popup.clone();
+        let button = gtk_menu.connect_button_release_event(move |_, event| {
+            button_popup.record(Input {
+                source: InputSource::Mouse,
+                modifiers: to_modifiers(event.state()),
+                escape: false,
+            });
+            glib::Propagation::Proceed
+        });
+        self.handlers.push((gtk_menu.clone(), button));
+
+        for child in gtk_menu.children() {
+            if let Some(submenu) = child.downcast_ref::<gtk::MenuItem>().and_then(|item| item.submenu()) {
+                if let Some(submenu) = submenu.downcast_ref::<gtk::Menu>() {
+                    self.connect(submenu, popup);
+                }
+            }
+        }
+    }
+
+    pub fn stop(self) {
+        for (gtk_menu, handler) in self.handlers {
+            gtk_menu.disconnect(handler);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn to_modifiers(state: ModifierType) -> Modifiers {
+    Modifiers {
+        shift: state.contains(ModifierType::SHIFT_MASK),
+        ctrl: state.contains(ModifierType::CONTROL_MASK),
+        alt: state.contains(ModifierType::MOD1_MASK),
+        meta: state.intersects(ModifierType::SUPER_MASK | ModifierType::META_MASK),
+    }
+}
+
+#[cfg(target_os = "windows")]
+const SUBCLASS_ID: usize = 0x5750;
+
+/// Records input on the menu window for as long as the window exists.
+/// Mouse and keyboard input of submenus is routed to the main menu window while it holds the capture.
+#[cfg(target_os = "windows")]
+pub fn track(window_handle: isize, popup: Arc<Popup>) {
+    let data = Box::into_raw(Box::new(popup));
+    let _ = unsafe { SetWindowSubclass(HWND(window_handle as _), Some(subclass_proc), SUBCLASS_ID, data as usize) };
+}
+
+#[cfg(target_os = "windows")]
+unsafe extern "system" fn subclass_proc(window: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM, _id: usize, data: usize) -> LRESULT {
+    let popup = &*(data as *const Arc<Popup>);
+
+    match msg {
+        WM_KEYDOWN => popup.record(Input {
+            source: InputSource::Keyboard,
+            modifiers: get_modifiers(),
+            // The Windows key closes the menu by opening the Start menu, which is reported as losing focus
+            escape: VIRTUAL_KEY(wparam.0 as u16) == VK_ESCAPE,
+        }),
+        WM_LBUTTONDOWN | WM_RBUTTONDOWN | WM_LBUTTONUP | WM_RBUTTONUP => popup.record(Input {
+            source: InputSource::Mouse,
+            modifiers: get_modifiers(),
+            escape: false,
+        }),
+        // Sent to every top-level window, so a theme change is seen as long as one menu exists
+        WM_SETTINGCHANGE if lparam.0 != 0 && PCWSTR::from_raw(lparam.0 as *const u16).to_string().is_ok_and(|area| area == "ImmersiveColorSet") => {
+            crate::theme::changed();
+        }
+        WM_NCDESTROY => {
+            let _ = RemoveWindowSubclass(window, Some(subclass_proc), SUBCLASS_ID);
+            drop(Box::from_raw(data as *mut Arc<Popup>));
+        }
+        _ => {}
+    }
+
+    DefSubclassProc(window, msg, wparam, lparam)
+}
+
+/// The keyboard hook of wcpopup swallows key messages, so the physical key state is used.
+#[cfg(target_os = "windows")]
+fn get_modifiers() -> Modifiers {
+    let is_down = |key: VIRTUAL_KEY| unsafe { GetAsyncKeyState(key.0 as i32) } < 0;
+    Modifiers {
+        shift: is_down(VK_SHIFT),
+        ctrl: is_down(VK_CONTROL),
+        alt: is_down(VK_MENU),
+        meta: is_down(VK_LWIN) || is_down(VK_RWIN),
+    }
+}
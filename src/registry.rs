@@ -0,0 +1,356 @@
+use crate::{error::MenuError, file::Watch, outcome::Input, theme};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+use wcpopup::{config::Theme, Menu, MenuType};
+
+/// State of a built menu, shared between the registry and a popup that is showing it.
+pub struct MenuState {
+    pub menu: Menu,
+    /// Ids of the submenus of a main menu, including those added later.
+    pub submenus: Vec<u32>,
+    /// Id of the main menu a submenu belongs to.
+    pub main: Option<u32>,
+    /// GTK window created to host a main menu.
+    #[cfg(target_os = "linux")]
+    pub window_handle: isize,
+    /// Window the main menu was built for, kept to build it again.
+    pub parent: u64,
+    /// Theme the app asked for, which may be System.
+    pub theme: Theme,
+    /// Shared by a main menu and its submenus.
+    pub popup: Arc<Popup>,
+    /// Polls the file a main menu was built from, when it is rebuilt on changes.
+    pub watch: Option<Watch>,
+}
+
+impl MenuState {
+    pub fn new(menu: Menu, popup: Arc<Popup>) -> Self {
+        Self {
+            menu,
+            submenus: Vec::new(),
+            main: None,
+            #[cfg(target_os = "linux")]
+            window_handle: 0,
+            parent: 0,
+            theme: Theme::System,
+            popup,
+            watch: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.popup.is_open()
+    }
+}
+
+/// Tracks the menu a popup is showing, the last input it received and why it was closed programmatically.
+#[derive(Default)]
+pub struct Popup {
+    inner: Mutex<PopupInner>,
+}
+
+#[derive(Default)]
+struct PopupInner {
+    shown: Option<Menu>,
+    input: Option<Input>,
+    cancel_reason: Option<String>,
+}
+
+impl Popup {
+    pub fn is_open(&self) -> bool {
+        lock(&self.inner).shown.is_some()
+    }
+
+    /// Returns false if the menu is already showing.
+    pub fn open(&self, menu: &Menu) -> bool {
+        let mut inner = lock(&self.inner);
+        if inner.shown.is_some() {
+            return false;
+        }
+        inner.shown = Some(menu.clone());
+        inner.input = None;
+        inner.cancel_reason = None;
+        true
+    }
+
+    pub fn record(&self, input: Input) {
+        let mut inner = lock(&self.inner);
+        if inner.shown.is_some() {
+            inner.input = Some(input);
+        }
+    }
+
+    /// Records the reason and returns the menu to close, if one is showing.
+    pub fn cancel(&self, reason: &str) -> Option<Menu> {
+        let mut inner = lock(&self.inner);
+        let shown = inner.shown.clone()?;
+        inner.cancel_reason.get_or_insert_with(|| reason.to_string());
+        Some(shown)
+    }
+
+    /// Marks the popup as closed and returns the last input along with the reason if it was cancelled.
+    pub fn finish(&self) -> (Option<Input>, Option<String>) {
+        let mut inner = lock(&self.inner);
+        inner.shown = None;
+        (inner.input.take(), inner.cancel_reason.take())
+    }
+}
+
+pub type SharedMenu = Arc<Mutex<MenuState>>;
+
+/// Menus by opaque id. Ids are never reused, so a stale handle cannot reach a newer menu.
+#[derive(Default)]
+struct Registry {
+    menus: HashMap<u32, SharedMenu>,
+    /// Ids by native menu handle, used to report submenus.
+    ids: HashMap<isize, u32>,
+    last_id: u32,
+}
+
+impl Registry {
+    fn next_id(&mut self) -> u32 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn insert(&mut self, id: u32, shared: SharedMenu, native_handle: isize) {
+        self.menus.insert(id, shared);
+        self.ids.insert(native_handle, id);
+    }
+
+    fn remove(&mut self, id: u32) -> Option<SharedMenu> {
+        let shared = self.menus.remove(&id)?;
+        self.ids.retain(|_, value| *value != id);
+        Some(shared)
+    }
+
+    /// The main menu of a menu, which is the menu itself unless it is a submenu.
+    fn main_of(&self, menu_handle: f64) -> Result<(u32, SharedMenu), MenuError> {
+        let id = to_id(menu_handle);
+        let shared = self.menus.get(&id).ok_or(MenuError::UnknownHandle(menu_handle))?;
+        let main_id = lock(shared).main.unwrap_or(id);
+        let main = self.menus.get(&main_id).cloned().ok_or(MenuError::UnknownHandle(menu_handle))?;
+        Ok((main_id, main))
+    }
+
+    fn insert_submenu(&mut self, submenu: Menu, main: &MenuState, main_id: u32) -> u32 {
+        let id = self.next_id();
+        let native = native_handle(&submenu);
+        let mut submenu = MenuState::new(submenu, main.popup.clone());
+        submenu.theme = main.theme;
+        submenu.main = Some(main_id);
+        self.insert(id, Arc::new(Mutex::new(submenu)), native);
+        id
+    }
+}
+
+/// The map is only locked for lookups, never while a menu is open.
+static MENU_MAP: Lazy<Mutex<Registry>> = Lazy::new(|| Mutex::new(Registry::default()));
+
+/// A panic while a lock was held leaves the state usable, so poisoning is ignored.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn native_handle(menu: &Menu) -> isize {
+    #[cfg(target_os = "linux")]
+    return menu.gtk_menu_handle;
+    #[cfg(target_os = "windows")]
+    return menu.window_handle;
+}
+
+/// Converts a JS handle to an id. Anything that is not a valid id matches no menu.
+fn to_id(menu_handle: f64) -> u32 {
+    if menu_handle.fract() == 0.0 && menu_handle >= 1.0 && menu_handle <= u32::MAX as f64 {
+        menu_handle as u32
+    } else {
+        0
+    }
+}
+
+/// Registers a main menu along with its submenus and returns its id.
+pub fn register(mut state: MenuState, submenus: Vec<Menu>) -> u32 {
+    let mut map = lock(&MENU_MAP);
+    let id = map.next_id();
+
+    for submenu in submenus {
+        let submenu_id = map.insert_submenu(submenu, &state, id);
+        state.submenus.push(submenu_id);
+    }
+
+    let native = native_handle(&state.menu);
+    map.insert(id, Arc::new(Mutex::new(state)), native);
+    id
+}
+
+/// Registers submenus added to a menu after it was built and returns their ids.
+///
+/// They belong to the main menu, even when added to one of its submenus, so that they are unregistered along with it.
+pub fn add_submenus(menu_handle: f64, submenus: Vec<Menu>) -> Result<Vec<u32>, MenuError> {
+    let mut map = lock(&MENU_MAP);
+    let (main_id, main) = map.main_of(menu_handle)?;
+    let mut main = lock(&main);
+
+    let ids: Vec<u32> = submenus.into_iter().map(|submenu| map.insert_submenu(submenu, &main, main_id)).collect();
+    main.submenus.extend(&ids);
+    Ok(ids)
+}
+
+/// Unregisters submenus whose items were removed from a menu.
+pub fn remove_submenus(menu_handle: f64, submenus: &[Menu]) -> Result<(), MenuError> {
+    let mut map = lock(&MENU_MAP);
+    let (_, main) = map.main_of(menu_handle)?;
+    let ids: Vec<u32> = submenus.iter().filter_map(|submenu| map.ids.get(&native_handle(submenu)).copied()).collect();
+    for id in &ids {
+        map.remove(*id);
+    }
+    lock(&main).submenus.retain(|id| !ids.contains(id));
+    Ok(())
+}
+
+/// Points the ids of submenus that were built again, such as those of a moved item, at the new menus.
+/// Both lists are in the order submenus_of gives.
+pub fn rebind_submenus(previous: &[Menu], rebuilt: Vec<Menu>) {
+    let mut map = lock(&MENU_MAP);
+    for (previous, submenu) in previous.iter().zip(rebuilt) {
+        let Some(id) = map.ids.remove(&native_handle(previous)) else {
+            continue;
+        };
+        map.ids.insert(native_handle(&submenu), id);
+        if let Some(entry) = map.menus.get(&id).cloned() {
+            lock(&entry).menu = submenu;
+        }
+    }
+}
+
+/// Submenus of the menu in the order a build lists them, nested ones before the submenu holding them.
+pub fn submenus_of(menu: &Menu, submenus: &mut Vec<Menu>) {
+    for item in menu.items() {
+        if let Some(submenu) = item.submenu {
+            submenus_of(&submenu, submenus);
+            submenus.push(submenu);
+        }
+    }
+}
+
+/// Returns the id of a registered menu.
+pub fn id_of(menu: &Menu) -> Option<u32> {
+    lock(&MENU_MAP).ids.get(&native_handle(menu)).copied()
+}
+
+pub fn get(menu_handle: f64) -> Result<SharedMenu, MenuError> {
+    lock(&MENU_MAP).menus.get(&to_id(menu_handle)).cloned().ok_or(MenuError::UnknownHandle(menu_handle))
+}
+
+/// Whether the menu is still registered under the handle.
+pub fn contains(menu_handle: f64, shared: &SharedMenu) -> bool {
+    lock(&MENU_MAP).menus.get(&to_id(menu_handle)).is_some_and(|entry| Arc::ptr_eq(entry, shared))
+}
+
+/// Unregisters a main menu along with its submenus.
+pub fn remove(menu_handle: f64) -> Option<SharedMenu> {
+    let mut map = lock(&MENU_MAP);
+    let shared = map.remove(to_id(menu_handle))?;
+    for submenu in &lock(&shared).submenus {
+        map.remove(*submenu);
+    }
+    Some(shared)
+}
+
+/// Swaps a rebuilt main menu and its submenus in under their existing ids and returns the previous state.
+///
+/// Submenus are matched in the order they appear in the menus, so that the ids of submenus added after
+/// the build follow them. If the rebuilt menu has more, the extra ones get new ids, and if it has fewer,
+/// the remaining ids are unregistered. The watch, if any, carries over.
+/// On failure the rebuilt state is handed back so that it can be torn down.
+pub fn replace(menu_handle: f64, mut state: MenuState, submenus: Vec<Menu>) -> Result<MenuState, (MenuError, Box<MenuState>)> {
+    let mut map = lock(&MENU_MAP);
+    let id = to_id(menu_handle);
+    let Some(shared) = map.menus.get(&id).cloned() else {
+        return Err((MenuError::UnknownHandle(menu_handle), Box::new(state)));
+    };
+    let mut current = lock(&shared);
+    if current.is_open() {
+        return Err((MenuError::MenuBusy, Box::new(state)));
+    }
+
+    let mut previous = Vec::new();
+    submenus_of(&current.menu, &mut previous);
+    let ids: Vec<u32> = previous.iter().filter_map(|submenu| map.ids.get(&native_handle(submenu)).copied()).collect();
+    // Submenus whose items were removed are no longer in the menu
+    for submenu_id in std::mem::take(&mut current.submenus) {
+        if !ids.contains(&submenu_id) {
+            map.remove(submenu_id);
+        }
+    }
+
+    let mut submenus = submenus.into_iter();
+    for submenu_id in ids {
+        let Some(submenu) = submenus.next() else {
+            map.remove(submenu_id);
+            continue;
+        };
+        if let Some(entry) = map.menus.get(&submenu_id).cloned() {
+            let mut entry = lock(&entry);
+            map.ids.remove(&native_handle(&entry.menu));
+            map.ids.insert(native_handle(&submenu), submenu_id);
+            entry.menu = submenu;
+            entry.theme = state.theme;
+            entry.popup = state.popup.clone();
+        }
+        state.submenus.push(submenu_id);
+    }
+    for submenu in submenus {
+        let submenu_id = map.insert_submenu(submenu, &state, id);
+        state.submenus.push(submenu_id);
+    }
+
+    map.ids.remove(&native_handle(&current.menu));
+    map.ids.insert(native_handle(&state.menu), id);
+    state.watch = current.watch.take();
+    Ok(std::mem::replace(&mut *current, state))
+}
+
+/// Applies the theme each main menu asked for again, resolving System against the current desktop settings.
+#[cfg(target_os = "linux")]
+pub fn restore_themes() {
+    let menus: Vec<SharedMenu> = lock(&MENU_MAP).menus.values().cloned().collect();
+    for shared in menus {
+        let state = lock(&shared);
+        if state.menu.menu_type == MenuType::Main {
+            state.menu.set_theme(theme::native_theme(state.theme));
+        }
+    }
+}
+
+/// Records the theme the app asked for on a main menu and its submenus.
+pub fn set_theme(menu_handle: f64, theme: Theme) -> Result<(), MenuError> {
+    let submenus = write(menu_handle, |state| {
+        state.menu.set_theme(theme::native_theme(theme));
+        state.theme = theme;
+        state.submenus.clone()
+    })?;
+    for submenu in submenus {
+        let _ = write(submenu as f64, |state| state.theme = theme);
+    }
+    Ok(())
+}
+
+pub fn read<T>(menu_handle: f64, f: impl FnOnce(&MenuState) -> T) -> Result<T, MenuError> {
+    let shared = get(menu_handle)?;
+    let state = lock(&shared);
+    Ok(f(&state))
+}
+
+/// Changes the menu unless it is open. Reads are allowed while the menu is showing.
+pub fn write<T>(menu_handle: f64, f: impl FnOnce(&mut MenuState) -> T) -> Result<T, MenuError> {
+    let shared = get(menu_handle)?;
+    let mut state = lock(&shared);
+    if state.is_open() {
+        return Err(MenuError::MenuBusy);
+    }
+    Ok(f(&mut state))
+}
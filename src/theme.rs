@@ -0,0 +1,364 @@
+use crate::{color, error::MenuError, registry::lock};
+#[cfg(target_os = "linux")]
+use gtk::{glib, prelude::GtkSettingsExt};
+use neon::{event::Channel, handle::Root, prelude::*, types::JsFunction};
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex};
+use wcpopup::config::{ColorScheme, Config, MenuSize, Theme, ThemeColor};
+#[cfg(target_os = "windows")]
+use windows::{
+    core::w,
+    Win32::{
+        Foundation::ERROR_SUCCESS,
+        System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD},
+    },
+};
+
+/// A callback registered with onSystemThemeChanged.
+struct Listener {
+    id: u32,
+    callback: Arc<Root<JsFunction>>,
+    channel: Channel,
+}
+
+#[derive(Default)]
+struct Watcher {
+    listeners: Vec<Listener>,
+    last_id: u32,
+    /// The last theme reported, so that repeated notifications are only reported once.
+    current: Option<Theme>,
+}
+
+impl Watcher {
+    /// Records the theme. Returns false if it is the one reported last.
+    fn update(&mut self, theme: Theme) -> bool {
+        if self.current == Some(theme) {
+            return false;
+        }
+        self.current = Some(theme);
+        true
+    }
+}
+
+static WATCHER: Lazy<Mutex<Watcher>> = Lazy::new(|| Mutex::new(Watcher::default()));
+
+/// Names that cannot be registered, in the order listThemes reports them.
+const BUILTIN_THEMES: [&str; 4] = ["dark", "light", "system", "high-contrast"];
+
+/// Presets added with registerTheme, in registration order.
+static PRESETS: Lazy<Mutex<Vec<(String, Config)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// What a theme name refers to.
+pub enum Preset {
+    /// The default config with the given theme.
+    Theme(Theme),
+    /// A complete config, including the theme it asks for.
+    Config(Config),
+}
+
+impl Preset {
+    pub fn into_config(self) -> Config {
+        match self {
+            Preset::Theme(theme) => Config {
+                theme,
+                ..Default::default()
+            },
+            Preset::Config(config) => config,
+        }
+    }
+}
+
+/// Looks up a built-in theme or a registered preset.
+pub fn preset(name: &str) -> Option<Preset> {
+    match name {
+        "dark" => Some(Preset::Theme(Theme::Dark)),
+        "light" => Some(Preset::Theme(Theme::Light)),
+        "system" => Some(Preset::Theme(Theme::System)),
+        "high-contrast" => Some(Preset::Config(high_contrast())),
+        _ => lock(&PRESETS).iter().find(|(preset, _)| preset == name).map(|(_, config)| Preset::Config(config.clone())),
+    }
+}
+
+/// Like preset, but unknown names fall back to System as plain theme names always have.
+pub fn to_preset(name: &str) -> Preset {
+    preset(name).unwrap_or(Preset::Theme(Theme::System))
+}
+
+/// Registers a preset, replacing any earlier one with the same name.
+///
+/// Menus already built from the earlier preset keep its config.
+pub fn register_preset(name: String, config: Config) -> Result<(), MenuError> {
+    if name.is_empty() {
+        return Err(MenuError::InvalidArgument("Theme name must not be empty".to_string()));
+    }
+    if BUILTIN_THEMES.contains(&name.as_str()) {
+        return Err(MenuError::InvalidArgument(format!("\"{}\" is a built-in theme", name)));
+    }
+
+    let mut presets = lock(&PRESETS);
+    match presets.iter_mut().find(|(preset, _)| *preset == name) {
+        Some(entry) => entry.1 = config,
+        None => presets.push((name, config)),
+    }
+    Ok(())
+}
+
+/// Names of the built-in themes followed by the registered presets.
+pub fn preset_names() -> Vec<String> {
+    BUILTIN_THEMES.iter().map(|name| name.to_string()).chain(lock(&PRESETS).iter().map(|(name, _)| name.clone())).collect()
+}
+
+/// White on black or black on white, depending on the system theme, with thicker borders.
+///
+/// The colors follow the Night sky and Desert contrast themes of Windows 11. wcpopup draws hovered items with
+/// the same text color, so the hover background is one the text stays as readable on as on the background.
+fn high_contrast() -> Config {
+    Config {
+        theme: Theme::System,
+        size: MenuSize {
+            border_size: 2,
+            ..Default::default()
+        },
+        color: ThemeColor {
+            dark: ColorScheme {
+                color: css("#ffffff"),
+                accelerator: css("#ffee32"),
+                border: css("#ffffff"),
+                separator: css("#ffffff"),
+                disabled: css("#a6a6a6"),
+                background_color: css("#000000"),
+                hover_background_color: css("#1a4a99"),
+            },
+            light: ColorScheme {
+                color: css("#000000"),
+                accelerator: css("#3d3d3d"),
+                border: css("#000000"),
+                separator: css("#000000"),
+                disabled: css("#676767"),
+                background_color: css("#ffffff"),
+                hover_background_color: css("#8fb8ff"),
+            },
+        },
+        ..Default::default()
+    }
+}
+
+/// Encodes a built-in color. They are all valid, as the tests check.
+fn css(value: &str) -> u32 {
+    color::parse(value).map_or(0, color::to_native)
+}
+
+/// Returns Dark or Light depending on the desktop settings.
+pub fn system_theme() -> Theme {
+    #[cfg(target_os = "linux")]
+    {
+        let dark = gtk::Settings::default().is_some_and(|settings| is_dark(settings.is_gtk_application_prefer_dark_theme(), settings.gtk_theme_name().as_deref()));
+        if dark {
+            Theme::Dark
+        } else {
+            Theme::Light
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let mut light = 1u32;
+        let mut size = std::mem::size_of::<u32>() as u32;
+        let result = unsafe {
+            RegGetValueW(
+                HKEY_CURRENT_USER,
+                w!("Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize"),
+                w!("AppsUseLightTheme"),
+                RRF_RT_REG_DWORD,
+                None,
+                Some(&mut light as *mut u32 as *mut _),
+                Some(&mut size),
+            )
+        };
+        if result == ERROR_SUCCESS && light == 0 {
+            Theme::Dark
+        } else {
+            Theme::Light
+        }
+    }
+}
+
+/// Dark variants of GTK themes are told apart by name, as Adwaita-dark is, when the app preference is not set.
+#[cfg(any(target_os = "linux", test))]
+fn is_dark(prefer_dark: bool, theme_name: Option<&str>) -> bool {
+    prefer_dark || theme_name.is_some_and(|name| name.to_lowercase().contains("dark"))
+}
+
+/// The theme to give wcpopup for the theme a menu asked for.
+///
+/// GTK menus built with Theme::System keep the colors of the GTK theme instead of the configured ones,
+/// so System is resolved to Dark or Light there. wcpopup resolves System itself on Windows.
+pub fn native_theme(theme: Theme) -> Theme {
+    #[cfg(target_os = "linux")]
+    if theme == Theme::System {
+        return system_theme();
+    }
+    theme
+}
+
+pub fn theme_name(theme: Theme) -> &'static str {
+    match theme {
+        Theme::Dark => "dark",
+        Theme::Light => "light",
+        Theme::System => "system",
+    }
+}
+
+/// Starts following the desktop settings. Calling it again does nothing.
+pub fn watch() {
+    #[cfg(target_os = "linux")]
+    {
+        static CONNECT: std::sync::Once = std::sync::Once::new();
+        CONNECT.call_once(|| {
+            if let Some(settings) = gtk::Settings::default() {
+                // wcpopup's own handlers force every menu to Dark or Light, so this runs after them
+                settings.connect_gtk_application_prefer_dark_theme_notify(|_| {
+                    glib::idle_add_local_once(changed);
+                });
+                settings.connect_gtk_theme_name_notify(|_| {
+                    glib::idle_add_local_once(changed);
+                });
+            }
+        });
+    }
+
+    let mut watcher = lock(&WATCHER);
+    if watcher.current.is_none() {
+        watcher.current = Some(system_theme());
+    }
+}
+
+/// Notifies the listeners if the system theme changed.
+///
+/// On Linux the theme of every menu is restored first. wcpopup already redraws System menus on Windows
+/// and leaves the others alone.
+pub fn changed() {
+    let theme = system_theme();
+    #[cfg(target_os = "linux")]
+    crate::registry::restore_themes();
+
+    let listeners: Vec<(Arc<Root<JsFunction>>, Channel)> = {
+        let mut watcher = lock(&WATCHER);
+        if !watcher.update(theme) {
+            return;
+        }
+        watcher.listeners.iter().map(|listener| (listener.callback.clone(), listener.channel.clone())).collect()
+    };
+
+    for (callback, channel) in listeners {
+        channel.send(move |mut cx| {
+            let callback = callback.to_inner(&mut cx);
+            let theme = cx.string(theme_name(theme));
+            callback.call_with(&cx).arg(theme).exec(&mut cx)
+        });
+    }
+}
+
+pub fn subscribe(callback: Root<JsFunction>, channel: Channel) -> u32 {
+    let mut watcher = lock(&WATCHER);
+    watcher.last_id += 1;
+    let id = watcher.last_id;
+    watcher.listeners.push(Listener {
+        id,
+        callback: Arc::new(callback),
+        channel,
+    });
+    id
+}
+
+/// Returns false if the listener was already removed.
+pub fn unsubscribe(id: u32) -> bool {
+    let mut watcher = lock(&WATCHER);
+    let count = watcher.listeners.len();
+    watcher.listeners.retain(|listener| listener.id != id);
+    watcher.listeners.len() != count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The WCAG contrast ratio of two colors.
+    fn contrast(a: u32, b: u32) -> f64 {
+        fn luminance(value: u32) -> f64 {
+            let rgba = color::from_native(value);
+            let linear = |channel: u8| {
+                let c = f64::from(channel) / 255.0;
+                if c <= 0.04045 {
+                    c / 12.92
+                } else {
+                    ((c + 0.055) / 1.055).powf(2.4)
+                }
+            };
+            0.2126 * linear(rgba.r) + 0.7152 * linear(rgba.g) + 0.0722 * linear(rgba.b)
+        }
+
+        let (a, b) = (luminance(a), luminance(b));
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    #[test]
+    fn built_in_colors_are_valid() {
+        assert_eq!(css("#ffffff"), 0xffffff);
+        assert_eq!(css("#1a4a99"), 0x1a4a99);
+        assert_eq!(css("not a color"), 0);
+    }
+
+    #[test]
+    fn high_contrast_keeps_text_readable() {
+        let config = high_contrast();
+        for scheme in [config.color.dark, config.color.light] {
+            for background in [scheme.background_color, scheme.hover_background_color] {
+                // WCAG AAA for text, AA for the secondary text
+                assert!(contrast(scheme.color, background) >= 7.0, "text on {:06x}", background);
+                assert!(contrast(scheme.accelerator, background) >= 4.5, "accelerator on {:06x}", background);
+            }
+            assert!(contrast(scheme.disabled, scheme.background_color) >= 4.5);
+            assert!(contrast(scheme.border, scheme.background_color) >= 7.0);
+            assert!(contrast(scheme.separator, scheme.background_color) >= 7.0);
+            // The hovered item has to stand out without relying on color alone
+            assert!(contrast(scheme.hover_background_color, scheme.background_color) >= 1.8);
+            // Disabled text is told apart from enabled text
+            assert!(contrast(scheme.disabled, scheme.color) >= 2.0);
+        }
+    }
+
+    #[test]
+    fn high_contrast_follows_the_system_theme() {
+        let config = high_contrast();
+        assert_eq!(config.theme, Theme::System);
+        assert_eq!(config.color.dark.background_color, 0x000000);
+        assert_eq!(config.color.light.background_color, 0xffffff);
+        assert_eq!(config.size.border_size, 2);
+    }
+
+    #[test]
+    fn dark_gtk_themes_are_recognized() {
+        assert!(is_dark(true, Some("Adwaita")));
+        assert!(is_dark(false, Some("Adwaita-dark")));
+        assert!(is_dark(false, Some("Yaru-Dark")));
+        assert!(!is_dark(false, Some("Adwaita")));
+        assert!(!is_dark(false, None));
+    }
+
+    #[test]
+    fn theme_changes_are_reported_once() {
+        let mut watcher = Watcher::default();
+        assert!(watcher.update(Theme::Dark));
+        assert!(!watcher.update(Theme::Dark));
+        assert!(watcher.update(Theme::Light));
+        assert_eq!(watcher.current, Some(Theme::Light));
+    }
+
+    #[test]
+    fn theme_names_match_the_typescript_names() {
+        assert_eq!(theme_name(Theme::Dark), "dark");
+        assert_eq!(theme_name(Theme::Light), "light");
+        assert_eq!(theme_name(Theme::System), "system");
+    }
+}
@@ -1,3 +1,13 @@
+use crate::{
+    color,
+    document::{Node, Value as NodeValue},
+    edit::{Operation, Output},
+    error::{Diagnostic, MenuError, MenuResultExt},
+    handle::{to_boxed, to_parent},
+    outcome::Outcome,
+    registry,
+    theme::{self, Preset},
+};
 use neon::{
     handle::Handle,
     prelude::*,
@@ -5,7 +15,7 @@ use neon::{
     types::{JsArray, JsBoolean, JsNumber, JsObject, JsString},
 };
 use wcpopup::{
-    config::{ColorScheme, Config, Corner, FontWeight, MenuFont, MenuSize, Theme, ThemeColor},
+    config::{ColorScheme, Config, Corner, FontWeight, Theme},
     Menu, MenuItem, MenuItemType, MenuType,
 };
 
@@ -19,114 +29,747 @@ pub struct ElectronMenuItem {
     pub submenu: Vec<ElectronMenuItem>,
     pub id: String,
     pub name: String,
+    /// The uuid of the native item the item was read from or returned as. The native item it is built into gets
+    /// the same one, so that items returned earlier are still found by remove. Otherwise wcpopup gives it a new one.
+    pub uuid: Option<u16>,
 }
 
+const ITEM_TYPES: [&str; 5] = ["normal", "separator", "submenu", "checkbox", "radio"];
+
+const THEMES: [&str; 3] = ["dark", "light", "system"];
+
 impl ElectronMenuItem {
-    pub fn from_object(cx: &mut FunctionContext, value: Handle<JsObject>) -> Self {
+    /// Parses template entries, pushing a Diagnostic for every invalid value instead of coercing it.
+    pub fn from_values(cx: &mut FunctionContext, values: Vec<Handle<JsValue>>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Vec<Self>> {
+        let mut items = Vec::new();
+        for (index, value) in values.into_iter().enumerate() {
+            let item_path = format!("{}[{}]", path, index);
+            match value.downcast::<JsObject, _>(cx) {
+                Ok(v) if !value.is_a::<JsArray, _>(cx) && !value.is_a::<JsFunction, _>(cx) => {
+                    items.push(ElectronMenuItem::from_object(cx, v, &item_path, diagnostics)?);
+                }
+                _ => diagnostics.push(Diagnostic::new(&item_path, format!("expected object, got {}", describe(cx, value)))),
+            }
+        }
+        Ok(items)
+    }
+
+    pub fn from_object(cx: &mut FunctionContext, value: Handle<JsObject>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Self> {
+        let submenu = match read_value::<JsArray>(cx, &value, "submenu", "array", path, diagnostics)? {
+            Some(array) => {
+                let values = array.to_vec(cx)?;
+                Some(ElectronMenuItem::from_values(cx, values, &format!("{}.submenu", path), diagnostics)?)
+            }
+            None => None,
+        };
+
+        let itype = match read_item_type(cx, &value, path, diagnostics)? {
+            Some(itype) => itype,
+            None if submenu.is_some() => "submenu".to_string(),
+            None => "normal".to_string(),
+        };
+
+        Ok(Self {
+            itype,
+            label: read_string(cx, &value, "label", path, diagnostics)?.unwrap_or_default(),
+            accelerator: read_string(cx, &value, "accelerator", path, diagnostics)?.unwrap_or_default(),
+            enabled: read_bool(cx, &value, "enabled", path, diagnostics)?.unwrap_or(true),
+            checked: read_bool(cx, &value, "checked", path, diagnostics)?.unwrap_or(false),
+            submenu: submenu.unwrap_or_default(),
+            id: read_string(cx, &value, "id", path, diagnostics)?.unwrap_or_default(),
+            name: read_string(cx, &value, "name", path, diagnostics)?.unwrap_or_default(),
+            uuid: None,
+        })
+    }
+
+    /// Parses template entries read from a file, following the same rules as from_values.
+    pub fn from_nodes(nodes: &[Node], path: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<Self> {
+        let mut items = Vec::new();
+        for (index, node) in nodes.iter().enumerate() {
+            let item_path = format!("{}[{}]", path, index);
+            if node.is_object() {
+                items.push(ElectronMenuItem::from_node(node, &item_path, diagnostics));
+            } else {
+                diagnostics.push(Diagnostic::new(&item_path, format!("expected object, got {}", node.describe())).at(node.position));
+            }
+        }
+        items
+    }
+
+    pub fn from_node(node: &Node, path: &str, diagnostics: &mut Vec<Diagnostic>) -> Self {
+        let submenu = match node.get("submenu") {
+            Some(Node {
+                value: NodeValue::Array(nodes),
+                ..
+            }) => Some(ElectronMenuItem::from_nodes(nodes, &format!("{}.submenu", path), diagnostics)),
+            Some(v) => {
+                diagnostics.push(Diagnostic::new(&format!("{}.submenu", path), format!("expected array, got {}", v.describe())).at(v.position));
+                None
+            }
+            None => None,
+        };
+
+        let itype = match node_string(node, "type", path, diagnostics) {
+            Some(itype) if ITEM_TYPES.contains(&itype.as_str()) => itype,
+            Some(itype) => {
+                let position = node.get("type").and_then(|v| v.position);
+                diagnostics.push(Diagnostic::new(&format!("{}.type", path), format!("expected one of {}, got \"{}\"", ITEM_TYPES.join("|"), itype)).at(position));
+                "normal".to_string()
+            }
+            None if submenu.is_some() => "submenu".to_string(),
+            None => "normal".to_string(),
+        };
+
+        Self {
+            itype,
+            label: node_string(node, "label", path, diagnostics).unwrap_or_default(),
+            accelerator: node_string(node, "accelerator", path, diagnostics).unwrap_or_default(),
+            enabled: node_bool(node, "enabled", path, diagnostics).unwrap_or(true),
+            checked: node_bool(node, "checked", path, diagnostics).unwrap_or(false),
+            submenu: submenu.unwrap_or_default(),
+            id: node_string(node, "id", path, diagnostics).unwrap_or_default(),
+            name: node_string(node, "name", path, diagnostics).unwrap_or_default(),
+            uuid: None,
+        }
+    }
+
+    /// Describes the items of a built menu, so that it can be built again.
+    pub fn from_menu_items(items: &[MenuItem]) -> Vec<Self> {
+        items.iter().map(|item| Self::from_native(item, item.submenu.as_ref().map(|submenu| Self::from_menu_items(&submenu.items())).unwrap_or_default())).collect()
+    }
+
+    /// Describes a native item whose submenu items, if any, have already been described.
+    pub fn from_native(item: &MenuItem, submenu: Vec<Self>) -> Self {
         Self {
-            itype: to_string(cx, &value, "type"),
-            label: to_string(cx, &value, "label"),
-            accelerator: to_string(cx, &value, "accelerator"),
-            enabled: to_bool(cx, &value, "enabled", true),
-            checked: to_bool(cx, &value, "checked", false),
-            submenu: value
-                .get_opt::<JsArray, _, _>(cx, "submenu")
-                .unwrap()
-                .unwrap_or_else(|| JsArray::new(cx, 0))
-                .to_vec(cx)
-                .unwrap()
-                .into_iter()
-                .map(|value| {
-                    let v = value.downcast_or_throw::<JsObject, _>(cx).unwrap();
+            itype: item_type_name(&item.menu_item_type).to_string(),
+            label: item.label.clone(),
+            accelerator: item.accelerator.clone(),
+            enabled: !item.disabled,
+            checked: item.checked,
+            submenu,
+            id: item.id.clone(),
+            name: item.name.clone(),
+            uuid: Some(item.uuid),
+        }
+    }
 
-                    ElectronMenuItem::from_object(cx, v)
-                })
-                .collect(),
-            id: to_string(cx, &value, "id"),
-            name: to_string(cx, &value, "name"),
+    /// The disabled argument wcpopup takes when it creates the item.
+    pub fn disabled(&self) -> Option<bool> {
+        if self.enabled {
+            None
+        } else {
+            Some(true)
         }
     }
+
+    /// Creates the native item. A submenu item starts out empty, its items are added once it is part of a menu.
+    pub fn to_native(&self) -> MenuItem {
+        let accelerator = if self.accelerator.is_empty() {
+            None
+        } else {
+            Some(self.accelerator.as_str())
+        };
+        let disabled = self.disabled();
+
+        let mut native = match self.itype.as_str() {
+            "separator" => MenuItem::new_separator(),
+            "submenu" => MenuItem::new_submenu_item(&self.id, &self.label, disabled),
+            "checkbox" => MenuItem::new_check_item(&self.id, &self.label, accelerator, self.checked, disabled),
+            "radio" => MenuItem::new_radio_item(&self.id, &self.label, &self.name, accelerator, self.checked, disabled),
+            _ => MenuItem::new_text_item(&self.id, &self.label, accelerator, disabled),
+        };
+        if let Some(uuid) = self.uuid {
+            native.uuid = uuid;
+        }
+        native
+    }
+
+    /// Writes the item as a template entry that from_values reads back into an equal item.
+    pub fn to_object<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsObject> {
+        from_node(cx, &self.to_entry())?.downcast_or_throw(cx)
+    }
+
+    /// Describes the item as a template entry that from_node reads back into an equal item.
+    ///
+    /// Only the fields that apply to the type are written. Empty ids and accelerators are left out,
+    /// and separators have no id since wcpopup does not keep one.
+    pub fn to_entry(&self) -> Node {
+        let mut entries = Vec::new();
+        let mut set = |key: &str, value: NodeValue| entries.push((key.to_string(), Node::new(value)));
+
+        if !self.id.is_empty() {
+            set("id", NodeValue::String(self.id.clone()));
+        }
+        set("type", NodeValue::String(self.itype.clone()));
+
+        if self.itype != "separator" {
+            set("label", NodeValue::String(self.label.clone()));
+            if !self.accelerator.is_empty() && self.itype != "submenu" {
+                set("accelerator", NodeValue::String(self.accelerator.clone()));
+            }
+            set("enabled", NodeValue::Bool(self.enabled));
+
+            match self.itype.as_str() {
+                "checkbox" | "radio" => {
+                    set("checked", NodeValue::Bool(self.checked));
+                    if self.itype == "radio" {
+                        set("name", NodeValue::String(self.name.clone()));
+                    }
+                }
+                "submenu" => set("submenu", NodeValue::Array(self.submenu.iter().map(Self::to_entry).collect())),
+                _ => {}
+            }
+        }
+
+        Node::new(NodeValue::Object(entries))
+    }
 }
 
-pub fn to_string(cx: &mut FunctionContext, value: &Handle<JsObject>, key: &str) -> String {
-    value.get_opt::<JsString, _, _>(cx, key).unwrap().unwrap_or_else(|| JsString::new(cx, "")).value(cx)
+/// Writes items back as a template that buildFromTemplate accepts.
+pub fn from_template<'a, C: Context<'a>>(cx: &mut C, items: &[ElectronMenuItem]) -> JsResult<'a, JsArray> {
+    let template = JsArray::new(cx, items.len());
+    for (index, item) in items.iter().enumerate() {
+        let obj = item.to_object(cx)?;
+        template.set(cx, index as u32, obj)?;
+    }
+    Ok(template)
 }
 
-pub fn to_bool(cx: &mut FunctionContext, value: &Handle<JsObject>, key: &str, def: bool) -> bool {
-    value.get_opt::<JsBoolean, _, _>(cx, key).unwrap().unwrap_or_else(|| JsBoolean::new(cx, def)).value(cx)
+/// Writes a Node as the JavaScript value it describes.
+fn from_node<'a, C: Context<'a>>(cx: &mut C, node: &Node) -> JsResult<'a, JsValue> {
+    Ok(match &node.value {
+        NodeValue::Null | NodeValue::Other(_) => cx.undefined().upcast(),
+        NodeValue::Bool(b) => cx.boolean(*b).upcast(),
+        NodeValue::Number(n) => cx.number(*n).upcast(),
+        NodeValue::String(s) => cx.string(s).upcast(),
+        NodeValue::Array(items) => {
+            let array = JsArray::new(cx, items.len());
+            for (index, item) in items.iter().enumerate() {
+                let value = from_node(cx, item)?;
+                array.set(cx, index as u32, value)?;
+            }
+            array.upcast()
+        }
+        NodeValue::Object(entries) => {
+            let obj = cx.empty_object();
+            for (key, value) in entries {
+                let value = from_node(cx, value)?;
+                obj.set(cx, key.as_str(), value)?;
+            }
+            obj.upcast()
+        }
+    })
 }
 
-pub fn to_i32(cx: &mut FunctionContext, value: &Handle<JsObject>, key: &str) -> i32 {
-    value.get_opt::<JsNumber, _, _>(cx, key).unwrap().unwrap_or_else(|| JsNumber::new(cx, 0)).value(cx) as i32
+/// Parses a whole template and throws InvalidTemplate listing every problem found.
+pub fn read_template(cx: &mut FunctionContext, values: Vec<Handle<JsValue>>) -> NeonResult<Vec<ElectronMenuItem>> {
+    let mut diagnostics = Vec::new();
+    let items = ElectronMenuItem::from_values(cx, values, "template", &mut diagnostics)?;
+    if diagnostics.is_empty() {
+        Ok(items)
+    } else {
+        MenuError::InvalidTemplate(diagnostics).throw(cx)
+    }
 }
 
-pub fn to_u32(cx: &mut FunctionContext, value: &Handle<JsObject>, key: &str) -> u32 {
-    value.get_opt::<JsNumber, _, _>(cx, key).unwrap().unwrap_or_else(|| JsNumber::new(cx, 0)).value(cx) as u32
+/// Collects the Diagnostics of a template without building anything.
+pub fn validate_template(cx: &mut FunctionContext, value: Handle<JsValue>) -> NeonResult<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    match value.downcast::<JsArray, _>(cx) {
+        Ok(array) => {
+            let values = array.to_vec(cx)?;
+            ElectronMenuItem::from_values(cx, values, "template", &mut diagnostics)?;
+        }
+        Err(_) => diagnostics.push(Diagnostic::new("template", format!("expected array, got {}", describe(cx, value)))),
+    }
+    Ok(diagnostics)
 }
 
-pub fn to_f32(cx: &mut FunctionContext, value: &Handle<JsObject>, key: &str) -> f32 {
-    value.get_opt::<JsNumber, _, _>(cx, key).unwrap().unwrap_or_else(|| JsNumber::new(cx, 0)).value(cx) as f32
+fn node_string(node: &Node, key: &str, path: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<String> {
+    match node.get(key) {
+        Some(Node {
+            value: NodeValue::String(string),
+            ..
+        }) => Some(string.clone()),
+        Some(v) => {
+            diagnostics.push(Diagnostic::new(&format!("{}.{}", path, key), format!("expected string, got {}", v.describe())).at(v.position));
+            None
+        }
+        None => None,
+    }
 }
 
-pub fn to_menu_item(cx: &mut FunctionContext, value: Handle<JsObject>) -> MenuItem {
-    let id = to_string(cx, &value, "id");
-    let label = to_string(cx, &value, "label");
-    let accelerator_str = to_string(cx, &value, "accelerator");
-    let name = to_string(cx, &value, "name");
-    let enabled = to_bool(cx, &value, "enabled", true);
-    let checked = to_bool(cx, &value, "checked", false);
+fn node_bool(node: &Node, key: &str, path: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<bool> {
+    match node.get(key) {
+        Some(Node {
+            value: NodeValue::Bool(b),
+            ..
+        }) => Some(*b),
+        Some(v) => {
+            diagnostics.push(Diagnostic::new(&format!("{}.{}", path, key), format!("expected boolean, got {}", v.describe())).at(v.position));
+            None
+        }
+        None => None,
+    }
+}
 
-    let accelerator = if accelerator_str.is_empty() {
-        None
+/// Objects nested deeper than any config are only described, which also stops at cycles.
+const MAX_NODE_DEPTH: usize = 8;
+
+/// Copies a JavaScript value into a Node. Only own enumerable properties of objects are copied.
+pub fn to_node(cx: &mut FunctionContext, value: Handle<JsValue>) -> NeonResult<Node> {
+    to_node_at(cx, value, 0)
+}
+
+fn to_node_at(cx: &mut FunctionContext, value: Handle<JsValue>, depth: usize) -> NeonResult<Node> {
+    let value = if is_nullish(cx, value) {
+        NodeValue::Null
+    } else if let Ok(v) = value.downcast::<JsString, _>(cx) {
+        NodeValue::String(v.value(cx))
+    } else if let Ok(v) = value.downcast::<JsNumber, _>(cx) {
+        NodeValue::Number(v.value(cx))
+    } else if let Ok(v) = value.downcast::<JsBoolean, _>(cx) {
+        NodeValue::Bool(v.value(cx))
+    } else if value.is_a::<JsFunction, _>(cx) {
+        NodeValue::Other("function")
+    } else if let Ok(array) = value.downcast::<JsArray, _>(cx) {
+        if depth >= MAX_NODE_DEPTH {
+            NodeValue::Other("array")
+        } else {
+            let mut items = Vec::new();
+            for item in array.to_vec(cx)? {
+                items.push(to_node_at(cx, item, depth + 1)?);
+            }
+            NodeValue::Array(items)
+        }
+    } else if let Ok(object) = value.downcast::<JsObject, _>(cx) {
+        if depth >= MAX_NODE_DEPTH {
+            NodeValue::Other("object")
+        } else {
+            let mut entries = Vec::new();
+            for key in object.get_own_property_names(cx)?.to_vec(cx)? {
+                let Ok(key) = key.downcast::<JsString, _>(cx) else {
+                    continue;
+                };
+                let key = key.value(cx);
+                let v = object.get_value(cx, key.as_str())?;
+                entries.push((key, to_node_at(cx, v, depth + 1)?));
+            }
+            NodeValue::Object(entries)
+        }
     } else {
-        Some(accelerator_str.as_str())
+        NodeValue::Other("object")
     };
-    let disabled = if enabled {
-        None
+    Ok(Node::new(value))
+}
+
+pub fn is_nullish(cx: &mut FunctionContext, value: Handle<JsValue>) -> bool {
+    value.is_a::<JsUndefined, _>(cx) || value.is_a::<JsNull, _>(cx)
+}
+
+pub fn describe(cx: &mut FunctionContext, value: Handle<JsValue>) -> String {
+    if let Ok(v) = value.downcast::<JsString, _>(cx) {
+        format!("\"{}\"", v.value(cx))
+    } else if let Ok(v) = value.downcast::<JsNumber, _>(cx) {
+        v.value(cx).to_string()
+    } else if let Ok(v) = value.downcast::<JsBoolean, _>(cx) {
+        v.value(cx).to_string()
+    } else if value.is_a::<JsNull, _>(cx) {
+        "null".to_string()
+    } else if value.is_a::<JsUndefined, _>(cx) {
+        "undefined".to_string()
+    } else if value.is_a::<JsArray, _>(cx) {
+        "array".to_string()
+    } else if value.is_a::<JsFunction, _>(cx) {
+        "function".to_string()
     } else {
-        Some(true)
+        "object".to_string()
+    }
+}
+
+fn read_value<'a, V: Value>(cx: &mut FunctionContext<'a>, value: &Handle<JsObject>, key: &str, expected: &str, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Option<Handle<'a, V>>> {
+    let v = value.get_value(cx, key)?;
+    if is_nullish(cx, v) {
+        return Ok(None);
+    }
+
+    match v.downcast::<V, _>(cx) {
+        Ok(v) => Ok(Some(v)),
+        Err(_) => {
+            diagnostics.push(Diagnostic::new(&format!("{}.{}", path, key), format!("expected {}, got {}", expected, describe(cx, v))));
+            Ok(None)
+        }
+    }
+}
+
+fn read_string(cx: &mut FunctionContext, value: &Handle<JsObject>, key: &str, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Option<String>> {
+    Ok(read_value::<JsString>(cx, value, key, "string", path, diagnostics)?.map(|v| v.value(cx)))
+}
+
+fn read_bool(cx: &mut FunctionContext, value: &Handle<JsObject>, key: &str, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Option<bool>> {
+    Ok(read_value::<JsBoolean>(cx, value, key, "boolean", path, diagnostics)?.map(|v| v.value(cx)))
+}
+
+fn read_item_type(cx: &mut FunctionContext, value: &Handle<JsObject>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Option<String>> {
+    match read_string(cx, value, "type", path, diagnostics)? {
+        Some(itype) if ITEM_TYPES.contains(&itype.as_str()) => Ok(Some(itype)),
+        Some(itype) => {
+            diagnostics.push(Diagnostic::new(&format!("{}.type", path), format!("expected one of {}, got \"{}\"", ITEM_TYPES.join("|"), itype)));
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+fn get_prop<'a, V: Value>(cx: &mut FunctionContext<'a>, value: &Handle<JsObject>, key: &str) -> NeonResult<Option<Handle<'a, V>>> {
+    let v = value.get_value(cx, key)?;
+    Ok(v.downcast::<V, _>(cx).ok())
+}
+
+/// Reads the uuid of an item returned earlier. Items without one keep the uuid wcpopup gives them.
+fn read_uuid(cx: &mut FunctionContext, value: &Handle<JsObject>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Option<u16>> {
+    let Some(uuid) = read_value::<JsNumber>(cx, value, "uuid", "number", path, diagnostics)? else {
+        return Ok(None);
     };
+    let uuid = uuid.value(cx);
+    match to_uuid(uuid) {
+        Some(uuid) => Ok(Some(uuid)),
+        None => {
+            diagnostics.push(Diagnostic::new(&format!("{}.uuid", path), format!("expected an integer from 0 to {}, got {}", u16::MAX, uuid)));
+            Ok(None)
+        }
+    }
+}
+
+fn to_uuid(value: f64) -> Option<u16> {
+    (value.fract() == 0.0 && (0.0..=u16::MAX as f64).contains(&value)).then_some(value as u16)
+}
 
-    let item_type_str = to_string(cx, &value, "type");
+pub fn to_menu_item(cx: &mut FunctionContext, value: Handle<JsObject>) -> NeonResult<MenuItem> {
+    Ok(to_new_item(cx, value)?.to_native())
+}
+
+/// Reads an item passed to append or insert, with the template of its submenu.
+pub fn to_new_item(cx: &mut FunctionContext, value: Handle<JsObject>) -> NeonResult<ElectronMenuItem> {
+    let mut diagnostics = Vec::new();
+    let item = read_new_item(cx, value, "item", &mut diagnostics)?;
+    if diagnostics.is_empty() {
+        Ok(item)
+    } else {
+        MenuError::InvalidTemplate(diagnostics).throw(cx)
+    }
+}
 
-    let menu_item_type = match item_type_str.as_str() {
-        "normal" => MenuItemType::Text,
-        "separator" => MenuItemType::Separator,
-        "submenu" => MenuItemType::Submenu,
-        "checkbox" => MenuItemType::Checkbox,
-        "radio" => MenuItemType::Radio,
-        _ => MenuItemType::Text,
+/// Anything but an array under `submenu`, such as the Menu of an item returned by items(), is ignored.
+fn read_new_item(cx: &mut FunctionContext, value: Handle<JsObject>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<ElectronMenuItem> {
+    let id = read_string(cx, &value, "id", path, diagnostics)?.unwrap_or_default();
+    let label = read_string(cx, &value, "label", path, diagnostics)?.unwrap_or_default();
+    let accelerator = read_string(cx, &value, "accelerator", path, diagnostics)?.unwrap_or_default();
+    let name = read_string(cx, &value, "name", path, diagnostics)?.unwrap_or_default();
+    let enabled = read_bool(cx, &value, "enabled", path, diagnostics)?.unwrap_or(true);
+    let checked = read_bool(cx, &value, "checked", path, diagnostics)?.unwrap_or(false);
+    let uuid = read_uuid(cx, &value, path, diagnostics)?;
+    let submenu = match get_prop::<JsArray>(cx, &value, "submenu")? {
+        Some(array) => {
+            let values = array.to_vec(cx)?;
+            Some(ElectronMenuItem::from_values(cx, values, &format!("{}.submenu", path), diagnostics)?)
+        }
+        None => None,
     };
+    let itype = match read_item_type(cx, &value, path, diagnostics)? {
+        Some(itype) => itype,
+        None if submenu.is_some() => "submenu".to_string(),
+        None => "normal".to_string(),
+    };
+
+    Ok(ElectronMenuItem {
+        itype,
+        label,
+        accelerator,
+        enabled,
+        checked,
+        submenu: submenu.unwrap_or_default(),
+        id,
+        name,
+        uuid,
+    })
+}
 
-    let mut item = match menu_item_type {
-        MenuItemType::Text => MenuItem::new_text_item(&id, &label, accelerator, disabled),
-        MenuItemType::Separator => MenuItem::new_separator(),
-        MenuItemType::Submenu => MenuItem::new_text_item(&id, &label, accelerator, disabled),
-        MenuItemType::Checkbox => MenuItem::new_check_item(&id, &label, accelerator, checked, disabled),
-        MenuItemType::Radio => MenuItem::new_radio_item(&id, &label, &name, accelerator, checked, disabled),
+/// The template name of the type.
+pub fn item_type_name(itype: &MenuItemType) -> &'static str {
+    match itype {
+        MenuItemType::Text => "normal",
+        MenuItemType::Separator => "separator",
+        MenuItemType::Submenu => "submenu",
+        MenuItemType::Checkbox => "checkbox",
+        MenuItemType::Radio => "radio",
+    }
+}
+
+/// Fields of an item changed by updateItem. Omitted fields keep their values.
+#[derive(Default)]
+pub struct ItemPatch {
+    pub label: Option<String>,
+    pub enabled: Option<bool>,
+    pub checked: Option<bool>,
+    pub accelerator: Option<String>,
+    pub name: Option<String>,
+}
+
+impl ItemPatch {
+    pub fn read(cx: &mut FunctionContext, value: Handle<JsObject>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Self> {
+        Ok(Self {
+            label: read_string(cx, &value, "label", path, diagnostics)?,
+            enabled: read_bool(cx, &value, "enabled", path, diagnostics)?,
+            checked: read_bool(cx, &value, "checked", path, diagnostics)?,
+            accelerator: read_string(cx, &value, "accelerator", path, diagnostics)?,
+            name: read_string(cx, &value, "name", path, diagnostics)?,
+        })
+    }
+
+    /// Reports the fields that items of the type, named as in templates, do not have.
+    pub fn check(&self, itype: &str) -> Result<(), MenuError> {
+        let checkable = itype == "checkbox" || itype == "radio";
+        let mut diagnostics = Vec::new();
+        let mut unsupported = |key: &str| diagnostics.push(Diagnostic::new(&format!("patch.{}", key), format!("not supported by {} items", itype)));
+
+        if self.label.is_some() && itype == "separator" {
+            unsupported("label");
+        }
+        if self.accelerator.is_some() && (itype == "separator" || itype == "submenu") {
+            unsupported("accelerator");
+        }
+        if self.checked.is_some() && !checkable {
+            unsupported("checked");
+        }
+        if self.name.is_some() && itype != "radio" {
+            unsupported("name");
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(MenuError::InvalidTemplate(diagnostics))
+        }
+    }
+
+    /// Whether the item has to be created again, since wcpopup can only change the label, enabled and checked in place.
+    /// A checked radio item is also created again to uncheck it, because checking another item is the only way otherwise.
+    pub fn needs_replace(&self, item: &MenuItem) -> bool {
+        self.accelerator.as_ref().is_some_and(|accelerator| *accelerator != item.accelerator)
+            || self.name.as_ref().is_some_and(|name| *name != item.name)
+            || (item.menu_item_type == MenuItemType::Radio && item.checked && self.checked == Some(false))
+    }
+
+    pub fn merge(&self, item: &mut ElectronMenuItem) {
+        if let Some(label) = &self.label {
+            item.label = label.clone();
+        }
+        if let Some(enabled) = self.enabled {
+            item.enabled = enabled;
+        }
+        if let Some(checked) = self.checked {
+            item.checked = checked;
+        }
+        if let Some(accelerator) = &self.accelerator {
+            item.accelerator = accelerator.clone();
+        }
+        if let Some(name) = &self.name {
+            item.name = name.clone();
+        }
+    }
+
+    /// Merges the patch into the item at the index among the items of its menu. Checking a radio item unchecks
+    /// the others in its group, as it does in wcpopup.
+    pub fn merge_at<'a>(&self, items: impl IntoIterator<Item = &'a mut ElectronMenuItem>, index: usize) {
+        let mut items: Vec<_> = items.into_iter().collect();
+        self.merge(items[index]);
+        if self.checked == Some(true) && items[index].itype == "radio" {
+            let name = items[index].name.clone();
+            for (other, item) in items.iter_mut().enumerate() {
+                if other != index && item.itype == "radio" && item.name == name {
+                    item.checked = false;
+                }
+            }
+        }
+    }
+}
+
+const OPERATIONS: [&str; 8] = ["append", "insert", "insertBefore", "insertAfter", "removeAt", "removeById", "updateItem", "moveItem"];
+
+/// Reads the operations passed to batch. Every invalid entry is reported before anything is applied.
+pub fn to_operations(cx: &mut FunctionContext, values: Vec<Handle<JsValue>>) -> NeonResult<Vec<Operation>> {
+    let mut diagnostics = Vec::new();
+    let mut operations = Vec::new();
+    for (index, value) in values.into_iter().enumerate() {
+        let path = format!("ops[{}]", index);
+        let value = match value.downcast::<JsObject, _>(cx) {
+            Ok(v) if !value.is_a::<JsArray, _>(cx) && !value.is_a::<JsFunction, _>(cx) => v,
+            _ => {
+                diagnostics.push(Diagnostic::new(&path, format!("expected object, got {}", describe(cx, value))));
+                continue;
+            }
+        };
+        if let Some(operation) = read_operation(cx, value, &path, &mut diagnostics)? {
+            operations.push(operation);
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(operations)
+    } else {
+        MenuError::InvalidTemplate(diagnostics).throw(cx)
+    }
+}
+
+fn read_operation(cx: &mut FunctionContext, value: Handle<JsObject>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Option<Operation>> {
+    let op = match read_string(cx, &value, "op", path, diagnostics)? {
+        Some(op) if OPERATIONS.contains(&op.as_str()) => op,
+        Some(op) => {
+            diagnostics.push(Diagnostic::new(&format!("{}.op", path), format!("expected one of {}, got \"{}\"", OPERATIONS.join("|"), op)));
+            return Ok(None);
+        }
+        None => {
+            diagnostics.push(Diagnostic::new(&format!("{}.op", path), "missing"));
+            return Ok(None);
+        }
+    };
+
+    let errors = diagnostics.len();
+    let operation = match op.as_str() {
+        "append" => read_operand_item(cx, &value, path, diagnostics)?.map(Operation::Append),
+        "insert" => {
+            let index = read_operand_index(cx, &value, path, diagnostics)?;
+            let item = read_operand_item(cx, &value, path, diagnostics)?;
+            index.zip(item).map(|(index, item)| Operation::Insert(index, item))
+        }
+        "insertBefore" | "insertAfter" => {
+            let id = read_operand_id(cx, &value, path, diagnostics)?;
+            let item = read_operand_item(cx, &value, path, diagnostics)?;
+            id.zip(item).map(|(id, item)| {
+                if op == "insertBefore" {
+                    Operation::InsertBefore(id, item)
+                } else {
+                    Operation::InsertAfter(id, item)
+                }
+            })
+        }
+        "removeAt" => read_operand_index(cx, &value, path, diagnostics)?.map(Operation::RemoveAt),
+        "removeById" => read_operand_id(cx, &value, path, diagnostics)?.map(Operation::RemoveById),
+        "updateItem" => {
+            let id = read_operand_id(cx, &value, path, diagnostics)?;
+            let before = diagnostics.len();
+            let patch = match read_value::<JsObject>(cx, &value, "patch", "object", path, diagnostics)? {
+                Some(patch) => Some(ItemPatch::read(cx, patch, &format!("{}.patch", path), diagnostics)?),
+                None => None,
+            };
+            let patch = required(patch, "patch", path, diagnostics, before);
+            id.zip(patch).map(|(id, patch)| Operation::Update(id, patch))
+        }
+        _ => {
+            let id = read_operand_id(cx, &value, path, diagnostics)?;
+            let target = read_string(cx, &value, "targetParentId", path, diagnostics)?;
+            let index = read_operand_index(cx, &value, path, diagnostics)?;
+            id.zip(index).map(|(id, index)| Operation::Move {
+                id,
+                target,
+                index,
+            })
+        }
+    };
+
+    Ok(operation.filter(|_| diagnostics.len() == errors))
+}
+
+fn read_operand_item(cx: &mut FunctionContext, value: &Handle<JsObject>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Option<ElectronMenuItem>> {
+    let errors = diagnostics.len();
+    let item = match read_value::<JsObject>(cx, value, "item", "object", path, diagnostics)? {
+        Some(item) => Some(read_new_item(cx, item, &format!("{}.item", path), diagnostics)?),
+        None => None,
+    };
+    Ok(required(item, "item", path, diagnostics, errors))
+}
+
+fn read_operand_id(cx: &mut FunctionContext, value: &Handle<JsObject>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Option<String>> {
+    let errors = diagnostics.len();
+    let id = read_string(cx, value, "id", path, diagnostics)?;
+    Ok(required(id, "id", path, diagnostics, errors))
+}
+
+fn read_operand_index(cx: &mut FunctionContext, value: &Handle<JsObject>, path: &str, diagnostics: &mut Vec<Diagnostic>) -> NeonResult<Option<usize>> {
+    let errors = diagnostics.len();
+    let index = match read_value::<JsNumber>(cx, value, "index", "number", path, diagnostics)? {
+        Some(index) => {
+            let index = index.value(cx);
+            if index >= 0.0 && index.fract() == 0.0 {
+                Some(index as usize)
+            } else {
+                diagnostics.push(Diagnostic::new(&format!("{}.index", path), format!("expected a non-negative integer, got {}", index)));
+                None
+            }
+        }
+        None => None,
     };
+    Ok(required(index, "index", path, diagnostics, errors))
+}
+
+/// Reports a field the operation needs when it was left out, rather than given a value of the wrong type.
+fn required<T>(value: Option<T>, key: &str, path: &str, diagnostics: &mut Vec<Diagnostic>, errors: usize) -> Option<T> {
+    if value.is_none() && diagnostics.len() == errors {
+        diagnostics.push(Diagnostic::new(&format!("{}.{}", path, key), "missing"));
+    }
+    value
+}
 
-    item.uuid = to_i32(cx, &value, "uuid") as u16;
-    item
+/// What an operation of batch returns: the handle of a new submenu, the updated item, or undefined.
+pub fn from_output<'a, C: Context<'a>>(cx: &mut C, output: &Output) -> JsResult<'a, JsValue> {
+    match output {
+        Output::None => Ok(cx.undefined().upcast()),
+        Output::Submenu(id) => Ok(cx.number(*id).upcast()),
+        Output::Item(item) => Ok(from_menu_item(cx, item)?.upcast()),
+    }
 }
 
-pub fn extract_item<'a, C: Context<'a>>(vec: &[MenuItem], cx: &mut C) -> JsResult<'a, JsArray> {
+/// Lists the items of each submenu under `items`, as far as `depth` levels down.
+pub fn extract_item<'a, C: Context<'a>>(vec: &[MenuItem], depth: u32, cx: &mut C) -> JsResult<'a, JsArray> {
     let items = JsArray::new(cx, vec.len());
     for (index, item) in vec.iter().enumerate() {
-        if item.menu_item_type == MenuItemType::Submenu {
-            let obj = from_menu_item(cx, item)?;
-            items.set(cx, index as u32, obj)?;
-            let submenus = item.submenu.as_ref().unwrap().items();
-            extract_item(&submenus, cx)?;
-        } else {
-            let obj = from_menu_item(cx, item)?;
-            items.set(cx, index as u32, obj)?;
+        let obj = from_menu_item(cx, item)?;
+        if depth > 0 {
+            if let Some(submenu) = &item.submenu {
+                let children = extract_item(&submenu.items(), depth - 1, cx)?;
+                obj.set(cx, "items", children)?;
+            }
         }
+        items.set(cx, index as u32, obj)?;
     }
 
     Ok(items)
 }
 
+/// Reads the `depth` option of items. Omitting it lists every level.
+pub fn depth_option(cx: &mut FunctionContext, i: usize) -> NeonResult<u32> {
+    let options = options_arg(cx, i)?;
+    let Some(depth) = options.get_opt::<JsNumber, _, _>(cx, "depth")?.map(|v| v.value(cx)) else {
+        return Ok(u32::MAX);
+    };
+    match to_depth(depth) {
+        Some(depth) => Ok(depth),
+        None => MenuError::InvalidArgument(format!("depth must be a non-negative integer, got {}", depth)).throw(cx),
+    }
+}
+
+/// Infinity lists every level, as does any depth beyond what a menu can nest.
+fn to_depth(depth: f64) -> Option<u32> {
+    if depth == f64::INFINITY {
+        Some(u32::MAX)
+    } else if depth >= 0.0 && depth.fract() == 0.0 {
+        Some(depth.min(u32::MAX as f64) as u32)
+    } else {
+        None
+    }
+}
+
 pub fn from_menu_item<'a, C: Context<'a>>(cx: &mut C, item: &MenuItem) -> JsResult<'a, JsObject> {
     let obj = cx.empty_object();
 
@@ -151,18 +794,11 @@ pub fn from_menu_item<'a, C: Context<'a>>(cx: &mut C, item: &MenuItem) -> JsResu
     let uuid = cx.number(item.uuid);
     obj.set(cx, "uuid", uuid)?;
 
-    let menu_item_type_str = match item.menu_item_type {
-        MenuItemType::Text => "normal",
-        MenuItemType::Separator => "separator",
-        MenuItemType::Submenu => "submenu",
-        MenuItemType::Checkbox => "checkbox",
-        MenuItemType::Radio => "radio",
-    };
-    let menu_item_type_str = cx.string(menu_item_type_str);
+    let menu_item_type_str = cx.string(item_type_name(&item.menu_item_type));
     obj.set(cx, "type", menu_item_type_str)?;
 
-    let submenu = if item.submenu.is_some() {
-        from_menu(cx, item.submenu.as_ref().unwrap())?
+    let submenu = if let Some(submenu) = &item.submenu {
+        from_menu(cx, submenu)?
     } else {
         cx.empty_object()
     };
@@ -171,13 +807,55 @@ pub fn from_menu_item<'a, C: Context<'a>>(cx: &mut C, item: &MenuItem) -> JsResu
     Ok(obj)
 }
 
+/// Result of a popup: the chosen item, if any, along with how the menu closed.
+pub fn from_outcome<'a, C: Context<'a>>(cx: &mut C, outcome: &Outcome) -> JsResult<'a, JsObject> {
+    let obj = match &outcome.item {
+        Some(item) => from_menu_item(cx, item)?,
+        None => cx.empty_object(),
+    };
+
+    let close_reason = cx.string(outcome.reason.as_str());
+    obj.set(cx, "closeReason", close_reason)?;
+
+    let path = JsArray::new(cx, outcome.path.len());
+    for (index, id) in outcome.path.iter().enumerate() {
+        let id = cx.string(id);
+        path.set(cx, index as u32, id)?;
+    }
+    obj.set(cx, "path", path)?;
+
+    let modifiers = outcome.input.map(|input| input.modifiers).unwrap_or_default();
+    let modifiers_obj = cx.empty_object();
+    let shift = cx.boolean(modifiers.shift);
+    modifiers_obj.set(cx, "shift", shift)?;
+    let ctrl = cx.boolean(modifiers.ctrl);
+    modifiers_obj.set(cx, "ctrl", ctrl)?;
+    let alt = cx.boolean(modifiers.alt);
+    modifiers_obj.set(cx, "alt", alt)?;
+    let meta = cx.boolean(modifiers.meta);
+    modifiers_obj.set(cx, "meta", meta)?;
+    obj.set(cx, "modifiers", modifiers_obj)?;
+
+    let input: Handle<JsValue> = match outcome.input {
+        Some(input) => cx.string(input.source.as_str()).upcast(),
+        None => cx.null().upcast(),
+    };
+    obj.set(cx, "input", input)?;
+
+    if let Some(reason) = &outcome.cancel_reason {
+        let cancelled = cx.boolean(true);
+        obj.set(cx, "cancelled", cancelled)?;
+        let reason = cx.string(reason);
+        obj.set(cx, "reason", reason)?;
+    }
+
+    Ok(obj)
+}
+
 pub fn from_menu<'a, C: Context<'a>>(cx: &mut C, menu: &Menu) -> JsResult<'a, JsObject> {
     let obj = cx.empty_object();
 
-    #[cfg(target_os = "windows")]
-    let menu_handle = cx.number(menu.window_handle as f64);
-    #[cfg(target_os = "linux")]
-    let menu_handle = cx.number(menu.gtk_menu_handle as f64);
+    let menu_handle = cx.number(registry::id_of(menu).unwrap_or(0));
     obj.set(cx, "menuWindowHandle", menu_handle)?;
 
     let type_str = match menu.menu_type {
@@ -190,93 +868,406 @@ pub fn from_menu<'a, C: Context<'a>>(cx: &mut C, menu: &Menu) -> JsResult<'a, Js
     Ok(obj)
 }
 
-pub fn to_config(cx: &mut FunctionContext, value: Handle<JsObject>) -> Config {
-    let theme = match to_string(cx, &value, "theme").as_str() {
+/// Options of `buildMenu`. The legacy build functions fill them from their positional arguments.
+pub struct BuildOptions<'a> {
+    pub parent: u64,
+    pub template: Vec<Handle<'a, JsValue>>,
+    pub config: Config,
+    pub boxed: bool,
+}
+
+/// The trailing `{ boxed?, strict? }` options of the legacy build functions, setConfig and registerTheme.
+/// They are read once, so every flag is held to the same rules: a missing argument means the defaults,
+/// and anything but an object is rejected.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrailingOptions {
+    pub boxed: bool,
+    pub strict: bool,
+}
+
+impl TrailingOptions {
+    pub fn read(cx: &mut FunctionContext, i: usize) -> NeonResult<Self> {
+        let options = options_arg(cx, i)?;
+        Ok(Self {
+            boxed: to_boxed(cx, &options)?,
+            strict: to_strict(cx, &options)?,
+        })
+    }
+}
+
+/// Reads `{ parent, template, theme?, config?, boxed?, strict? }`.
+pub fn to_build_options<'a>(cx: &mut FunctionContext<'a>, value: Handle<JsObject>) -> NeonResult<BuildOptions<'a>> {
+    let parent = value.get_value(cx, "parent")?;
+    if is_nullish(cx, parent) {
+        return MenuError::InvalidArgument("\"parent\" is required".to_string()).throw(cx);
+    }
+    let parent = to_parent(cx, parent)?;
+
+    let template = value.get_value(cx, "template")?;
+    let template = match template.downcast::<JsArray, _>(cx) {
+        Ok(template) => template.to_vec(cx)?,
+        Err(_) => {
+            let message = format!("expected array, got {}", describe(cx, template));
+            return MenuError::InvalidTemplate(vec![Diagnostic::new("template", message)]).throw(cx);
+        }
+    };
+
+    let config = StyleOptions::read(cx, &value)?.to_config(None).or_throw(cx)?;
+    let boxed = to_boxed(cx, &value)?;
+
+    Ok(BuildOptions {
+        parent,
+        template,
+        config,
+        boxed,
+    })
+}
+
+/// The `theme`, `config` and `strict` build options, read ahead so that a menu file can be built again.
+#[derive(Debug, Clone, Default)]
+pub struct StyleOptions {
+    pub theme: Option<String>,
+    pub config: Option<Node>,
+    pub strict: bool,
+}
+
+impl StyleOptions {
+    pub fn read(cx: &mut FunctionContext, value: &Handle<JsObject>) -> NeonResult<Self> {
+        let theme = get_prop::<JsString>(cx, value, "theme")?.map(|theme| theme.value(cx));
+        let config = match get_prop::<JsObject>(cx, value, "config")? {
+            Some(config) => Some(to_node(cx, config.upcast())?),
+            None => None,
+        };
+        Ok(Self {
+            theme,
+            config,
+            strict: to_strict(cx, value)?,
+        })
+    }
+
+    /// A preset named by the theme is the base that the embedded config, then the config option, are merged over.
+    /// Plain themes take precedence over both configs.
+    pub fn to_config(&self, embedded: Option<&Node>) -> Result<Config, MenuError> {
+        let preset = match &self.theme {
+            Some(theme) if self.strict && theme::preset(theme).is_none() => {
+                return Err(MenuError::InvalidConfig(vec![Diagnostic::new("theme", format!("unknown \"{}\"", theme))]));
+            }
+            Some(theme) => Some(theme::to_preset(theme)),
+            None => None,
+        };
+
+        let mut config = match &preset {
+            Some(Preset::Config(config)) => config.clone(),
+            _ => Config::default(),
+        };
+        for node in [embedded, self.config.as_ref()].into_iter().flatten() {
+            merge_node_config(node, &mut config, self.strict)?;
+        }
+        if let Some(Preset::Theme(theme)) = preset {
+            config.theme = theme;
+        }
+        Ok(config)
+    }
+}
+
+/// Reads the optional options object at argument `i`.
+pub fn options_arg<'a>(cx: &mut FunctionContext<'a>, i: usize) -> NeonResult<Handle<'a, JsObject>> {
+    match cx.argument_opt(i) {
+        Some(value) if !is_nullish(cx, value) => value.downcast_or_throw::<JsObject, _>(cx),
+        _ => Ok(cx.empty_object()),
+    }
+}
+
+pub fn to_theme(theme: &str) -> Theme {
+    match theme {
         "dark" => Theme::Dark,
         "light" => Theme::Light,
         "system" => Theme::System,
         _ => Theme::System,
-    };
+    }
+}
 
-    let size_obj = value.get::<JsObject, _, _>(cx, "size").unwrap();
+/// Reads a partial config. Omitted sections and fields keep their values from `Config::default()`.
+pub fn to_config(cx: &mut FunctionContext, value: Handle<JsObject>, strict: bool) -> NeonResult<Config> {
+    let mut config = Config::default();
+    merge_config(cx, value, &mut config, strict)?;
+    Ok(config)
+}
 
-    let size = MenuSize {
-        border_size: to_i32(cx, &size_obj, "borderSize"),
-        vertical_padding: to_i32(cx, &size_obj, "verticalPadding"),
-        horizontal_padding: to_i32(cx, &size_obj, "horizontalPadding"),
-        item_vertical_padding: to_i32(cx, &size_obj, "itemVerticalPadding"),
-        item_horizontal_padding: to_i32(cx, &size_obj, "itemHorizontalPadding"),
-        submenu_offset: to_i32(cx, &size_obj, "submenuOffset"),
-    };
+/// Overwrites the config with the fields present in the value and throws InvalidConfig listing every problem found.
+pub fn merge_config(cx: &mut FunctionContext, value: Handle<JsObject>, config: &mut Config, strict: bool) -> NeonResult<()> {
+    let node = to_node(cx, value.upcast())?;
+    merge_node_config(&node, config, strict).or_throw(cx)
+}
 
-    let font_obj = value.get::<JsObject, _, _>(cx, "font").unwrap();
-    let font = MenuFont {
-        font_family: to_string(cx, &font_obj, "fontFamily"),
-        dark_font_size: to_f32(cx, &font_obj, "darkFontSize"),
-        dark_font_weight: match to_string(cx, &font_obj, "darkFontWeight").as_str() {
-            "Thin" => FontWeight::Thin,
-            "Light" => FontWeight::Light,
-            "Normal" => FontWeight::Normal,
-            "Medium" => FontWeight::Medium,
-            "Bold" => FontWeight::Bold,
-            _ => FontWeight::Normal,
-        },
-        light_font_size: to_f32(cx, &font_obj, "lightFontSize"),
-        light_font_weight: match to_string(cx, &font_obj, "lightFontWeight").as_str() {
-            "Thin" => FontWeight::Thin,
-            "Light" => FontWeight::Light,
-            "Normal" => FontWeight::Normal,
-            "Medium" => FontWeight::Medium,
-            "Bold" => FontWeight::Bold,
-            _ => FontWeight::Normal,
-        },
-    };
+/// Overwrites the config with the fields present in the node, failing with InvalidConfig listing every problem found.
+pub fn merge_node_config(node: &Node, config: &mut Config, strict: bool) -> Result<(), MenuError> {
+    let diagnostics = read_config(node, config, strict);
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(MenuError::InvalidConfig(diagnostics))
+    }
+}
 
-    let color_obj = value.get::<JsObject, _, _>(cx, "color").unwrap();
-    let dark_color_scheme_obj = color_obj.get::<JsObject, _, _>(cx, "dark").unwrap();
-    let light_color_scheme_obj = color_obj.get::<JsObject, _, _>(cx, "light").unwrap();
-
-    let dark = ColorScheme {
-        color: to_u32(cx, &dark_color_scheme_obj, "color"),
-        accelerator: to_u32(cx, &dark_color_scheme_obj, "accelerator"),
-        border: to_u32(cx, &dark_color_scheme_obj, "border"),
-        separator: to_u32(cx, &dark_color_scheme_obj, "separator"),
-        disabled: to_u32(cx, &dark_color_scheme_obj, "disabled"),
-        background_color: to_u32(cx, &dark_color_scheme_obj, "backgroundColor"),
-        hover_background_color: to_u32(cx, &dark_color_scheme_obj, "hoverBackgroundColor"),
-    };
+/// Collects the Diagnostics of a config, including the checks of strict mode.
+pub fn validate_config(cx: &mut FunctionContext, value: Handle<JsValue>) -> NeonResult<Vec<Diagnostic>> {
+    match value.downcast::<JsObject, _>(cx) {
+        Ok(_) if !value.is_a::<JsArray, _>(cx) && !value.is_a::<JsFunction, _>(cx) => {
+            let node = to_node(cx, value)?;
+            Ok(read_config(&node, &mut Config::default(), true))
+        }
+        _ => Ok(vec![Diagnostic::new("config", format!("expected object, got {}", describe(cx, value)))]),
+    }
+}
 
-    let light = ColorScheme {
-        color: to_u32(cx, &light_color_scheme_obj, "color"),
-        accelerator: to_u32(cx, &light_color_scheme_obj, "accelerator"),
-        border: to_u32(cx, &light_color_scheme_obj, "border"),
-        separator: to_u32(cx, &light_color_scheme_obj, "separator"),
-        disabled: to_u32(cx, &light_color_scheme_obj, "disabled"),
-        background_color: to_u32(cx, &light_color_scheme_obj, "backgroundColor"),
-        hover_background_color: to_u32(cx, &light_color_scheme_obj, "hoverBackgroundColor"),
+fn read_config(value: &Node, config: &mut Config, strict: bool) -> Vec<Diagnostic> {
+    let mut reader = ConfigReader {
+        strict,
+        diagnostics: Vec::new(),
     };
 
-    let color = ThemeColor {
-        dark,
-        light,
-    };
+    if let Some(theme) = reader.theme(value) {
+        config.theme = theme;
+    }
+
+    if let Some(size_obj) = reader.section(value, "", "size") {
+        let size = &mut config.size;
+        reader.size(size_obj, "borderSize", &mut size.border_size);
+        reader.size(size_obj, "verticalPadding", &mut size.vertical_padding);
+        reader.size(size_obj, "horizontalPadding", &mut size.horizontal_padding);
+        reader.size(size_obj, "itemVerticalPadding", &mut size.item_vertical_padding);
+        reader.size(size_obj, "itemHorizontalPadding", &mut size.item_horizontal_padding);
+        if let Some(offset) = reader.number(size_obj, "size", "submenuOffset") {
+            size.submenu_offset = offset as i32;
+        }
+    }
+
+    if let Some(font_obj) = reader.section(value, "", "font") {
+        let font = &mut config.font;
+        if let Some(family) = reader.string(font_obj, "font", "fontFamily") {
+            font.font_family = family;
+        }
+        reader.font_size(font_obj, "darkFontSize", &mut font.dark_font_size);
+        reader.font_weight(font_obj, "darkFontWeight", &mut font.dark_font_weight);
+        reader.font_size(font_obj, "lightFontSize", &mut font.light_font_size);
+        reader.font_weight(font_obj, "lightFontWeight", &mut font.light_font_weight);
+    }
 
-    let corner = if to_string(cx, &value, "corner") == "Round" {
-        Corner::Round
+    if let Some(color_obj) = reader.section(value, "", "color") {
+        if let Some(dark_obj) = reader.section(color_obj, "color", "dark") {
+            reader.color_scheme(dark_obj, "color.dark", &mut config.color.dark);
+        }
+        if let Some(light_obj) = reader.section(color_obj, "color", "light") {
+            reader.color_scheme(light_obj, "color.light", &mut config.color.light);
+        }
+    }
+
+    if let Some(corner) = reader.corner(value) {
+        config.corner = corner;
+    }
+
+    reader.diagnostics
+}
+
+/// Reads config values, keeping the current value when a property is missing.
+///
+/// Values that cannot be used at all are always reported. Wrong types, out-of-range numbers and
+/// unknown names are only reported in strict mode and otherwise fall back as they always have.
+struct ConfigReader {
+    strict: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ConfigReader {
+    fn diagnostic(&mut self, path: &str, node: &Node, message: String) {
+        self.diagnostics.push(Diagnostic::new(path, message).at(node.position));
+    }
+
+    fn strict_diagnostic(&mut self, path: &str, node: &Node, message: String) {
+        if self.strict {
+            self.diagnostic(path, node, message);
+        }
+    }
+
+    /// A section that is not an object is always an error.
+    fn section<'a>(&mut self, value: &'a Node, path: &str, key: &str) -> Option<&'a Node> {
+        let v = value.get(key)?;
+        if v.is_object() {
+            Some(v)
+        } else {
+            self.diagnostic(&join_path(path, key), v, format!("expected object, got {}", v.describe()));
+            None
+        }
+    }
+
+    fn string(&mut self, value: &Node, path: &str, key: &str) -> Option<String> {
+        let v = value.get(key)?;
+        self.as_string(v, &join_path(path, key))
+    }
+
+    fn as_string(&mut self, v: &Node, path: &str) -> Option<String> {
+        match &v.value {
+            NodeValue::String(string) => Some(string.clone()),
+            _ => {
+                self.strict_diagnostic(path, v, format!("expected string, got {}", v.describe()));
+                None
+            }
+        }
+    }
+
+    fn number(&mut self, value: &Node, path: &str, key: &str) -> Option<f64> {
+        let v = value.get(key)?;
+        self.as_number(v, &join_path(path, key))
+    }
+
+    /// NaN and infinities are skipped rather than truncated.
+    fn as_number(&mut self, v: &Node, path: &str) -> Option<f64> {
+        match v.value {
+            NodeValue::Number(number) if number.is_finite() => Some(number),
+            _ => {
+                self.strict_diagnostic(path, v, format!("expected a finite number, got {}", v.describe()));
+                None
+            }
+        }
+    }
+
+    fn size(&mut self, value: &Node, key: &str, target: &mut i32) {
+        let Some(v) = value.get(key) else {
+            return;
+        };
+        let path = join_path("size", key);
+        if let Some(size) = self.as_number(v, &path) {
+            if size < 0.0 {
+                self.strict_diagnostic(&path, v, format!("expected a non-negative number, got {}", size));
+            }
+            *target = size as i32;
+        }
+    }
+
+    fn font_size(&mut self, value: &Node, key: &str, target: &mut f32) {
+        let Some(v) = value.get(key) else {
+            return;
+        };
+        let path = join_path("font", key);
+        if let Some(size) = self.as_number(v, &path) {
+            if size <= 0.0 {
+                self.strict_diagnostic(&path, v, format!("expected a positive number, got {}", size));
+            }
+            *target = size as f32;
+        }
+    }
+
+    fn font_weight(&mut self, value: &Node, key: &str, target: &mut FontWeight) {
+        let Some(v) = value.get(key) else {
+            return;
+        };
+        let path = join_path("font", key);
+        if let Some(weight) = self.as_string(v, &path) {
+            *target = match weight.as_str() {
+                "Thin" => FontWeight::Thin,
+                "Light" => FontWeight::Light,
+                "Normal" => FontWeight::Normal,
+                "Medium" => FontWeight::Medium,
+                "Bold" => FontWeight::Bold,
+                _ => {
+                    self.strict_diagnostic(&path, v, format!("unknown \"{}\"", weight));
+                    FontWeight::Normal
+                }
+            };
+        }
+    }
+
+    fn theme(&mut self, value: &Node) -> Option<Theme> {
+        let v = value.get("theme")?;
+        let theme = self.as_string(v, "theme")?;
+        if !THEMES.contains(&theme.as_str()) {
+            self.strict_diagnostic("theme", v, format!("unknown \"{}\"", theme));
+        }
+        Some(to_theme(&theme))
+    }
+
+    fn corner(&mut self, value: &Node) -> Option<Corner> {
+        let v = value.get("corner")?;
+        let corner = self.as_string(v, "corner")?;
+        match corner.as_str() {
+            "Round" => Some(Corner::Round),
+            "DoNotRound" => Some(Corner::DoNotRound),
+            _ => {
+                self.strict_diagnostic("corner", v, format!("unknown \"{}\"", corner));
+                Some(Corner::DoNotRound)
+            }
+        }
+    }
+
+    fn color_scheme(&mut self, value: &Node, path: &str, scheme: &mut ColorScheme) {
+        self.color(value, path, "color", &mut scheme.color);
+        self.color(value, path, "accelerator", &mut scheme.accelerator);
+        self.color(value, path, "border", &mut scheme.border);
+        self.color(value, path, "separator", &mut scheme.separator);
+        self.color(value, path, "disabled", &mut scheme.disabled);
+        self.color(value, path, "backgroundColor", &mut scheme.background_color);
+        self.color(value, path, "hoverBackgroundColor", &mut scheme.hover_background_color);
+    }
+
+    /// Accepts a CSS color string or a number already in the native layout. An unparsable string is always an error.
+    fn color(&mut self, value: &Node, path: &str, key: &str, target: &mut u32) {
+        let Some(v) = value.get(key) else {
+            return;
+        };
+        let path = join_path(path, key);
+        match &v.value {
+            NodeValue::Number(number) => {
+                if number.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(number) {
+                    self.strict_diagnostic(&path, v, format!("expected a color, got {}", number));
+                }
+                *target = *number as u32;
+            }
+            NodeValue::String(string) => match color::parse(string) {
+                Some(rgba) => *target = color::to_native(rgba),
+                None => self.diagnostic(&path, v, format!("invalid color \"{}\"", string)),
+            },
+            _ => self.strict_diagnostic(&path, v, format!("expected string or number, got {}", v.describe())),
+        }
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
     } else {
-        Corner::DoNotRound
-    };
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Reads `strict` from trailing options. Left out, it is false; any value other than a boolean is rejected.
+pub fn to_strict(cx: &mut FunctionContext, options: &Handle<JsObject>) -> NeonResult<bool> {
+    let strict = options.get_value(cx, "strict")?;
+    if let Ok(strict) = strict.downcast::<JsBoolean, _>(cx) {
+        return Ok(strict.value(cx));
+    }
+    if is_nullish(cx, strict) {
+        return Ok(false);
+    }
+    let got = describe(cx, strict);
+    MenuError::InvalidArgument(format!("strict must be a boolean, got {}", got)).throw(cx)
+}
 
-    Config {
-        theme,
-        size,
-        color,
-        corner,
-        font,
+/// Reads `colorFormat` from the options of getDefaultConfig. Returns true for "hex".
+pub fn hex_colors_option(cx: &mut FunctionContext, i: usize) -> NeonResult<bool> {
+    let options = match cx.argument_opt(i) {
+        Some(value) if !is_nullish(cx, value) => value.downcast_or_throw::<JsObject, _>(cx)?,
+        _ => return Ok(false),
+    };
+    match get_prop::<JsString>(cx, &options, "colorFormat")?.map(|v| v.value(cx)).as_deref() {
+        None | Some("number") => Ok(false),
+        Some("hex") => Ok(true),
+        Some(format) => MenuError::InvalidArgument(format!("unknown colorFormat \"{}\"", format)).throw(cx),
     }
 }
 
-pub fn from_config<'a, C: Context<'a>>(cx: &mut C, config: &Config) -> JsResult<'a, JsObject> {
+/// Emits colors as `#rrggbb[aa]` strings if `hex_colors` is set, or as native numbers otherwise.
+pub fn from_config<'a, C: Context<'a>>(cx: &mut C, config: &Config, hex_colors: bool) -> JsResult<'a, JsObject> {
     let configjs = cx.empty_object();
 
     let theme_str = match config.theme {
@@ -305,38 +1296,9 @@ pub fn from_config<'a, C: Context<'a>>(cx: &mut C, config: &Config) -> JsResult<
     configjs.set(cx, "size", size)?;
 
     let color = cx.empty_object();
-    let dark = cx.empty_object();
-    let a = cx.number(config.color.dark.color);
-    dark.set(cx, "color", a)?;
-    let a = cx.number(config.color.dark.accelerator);
-    dark.set(cx, "accelerator", a)?;
-    let a = cx.number(config.color.dark.border);
-    dark.set(cx, "border", a)?;
-    let a = cx.number(config.color.dark.separator);
-    dark.set(cx, "separator", a)?;
-    let a = cx.number(config.color.dark.disabled);
-    dark.set(cx, "disabled", a)?;
-    let a = cx.number(config.color.dark.background_color);
-    dark.set(cx, "backgroundColor", a)?;
-    let a = cx.number(config.color.dark.hover_background_color);
-    dark.set(cx, "hoverBackgroundColor", a)?;
+    let dark = from_color_scheme(cx, &config.color.dark, hex_colors)?;
     color.set(cx, "dark", dark)?;
-
-    let light = cx.empty_object();
-    let a = cx.number(config.color.light.color);
-    light.set(cx, "color", a)?;
-    let a = cx.number(config.color.light.accelerator);
-    light.set(cx, "accelerator", a)?;
-    let a = cx.number(config.color.light.border);
-    light.set(cx, "border", a)?;
-    let a = cx.number(config.color.light.separator);
-    light.set(cx, "separator", a)?;
-    let a = cx.number(config.color.light.disabled);
-    light.set(cx, "disabled", a)?;
-    let a = cx.number(config.color.light.background_color);
-    light.set(cx, "backgroundColor", a)?;
-    let a = cx.number(config.color.light.hover_background_color);
-    light.set(cx, "hoverBackgroundColor", a)?;
+    let light = from_color_scheme(cx, &config.color.light, hex_colors)?;
     color.set(cx, "light", light)?;
 
     configjs.set(cx, "color", color)?;
@@ -377,3 +1339,379 @@ pub fn from_config<'a, C: Context<'a>>(cx: &mut C, config: &Config) -> JsResult<
 
     Ok(configjs)
 }
+
+fn from_color_scheme<'a, C: Context<'a>>(cx: &mut C, scheme: &ColorScheme, hex_colors: bool) -> JsResult<'a, JsObject> {
+    let obj = cx.empty_object();
+    let colors = [
+        ("color", scheme.color),
+        ("accelerator", scheme.accelerator),
+        ("border", scheme.border),
+        ("separator", scheme.separator),
+        ("disabled", scheme.disabled),
+        ("backgroundColor", scheme.background_color),
+        ("hoverBackgroundColor", scheme.hover_background_color),
+    ];
+    for (key, value) in colors {
+        let value: Handle<JsValue> = if hex_colors {
+            cx.string(color::to_hex(color::from_native(value))).upcast()
+        } else {
+            cx.number(value).upcast()
+        };
+        obj.set(cx, key, value)?;
+    }
+    Ok(obj)
+}
+
+/// Items to build templates from in tests, labelled after their ids.
+#[cfg(test)]
+pub mod test_items {
+    use super::ElectronMenuItem;
+    use crate::document::Node;
+
+    pub fn item(id: &str, itype: &str) -> ElectronMenuItem {
+        ElectronMenuItem {
+            itype: itype.to_string(),
+            label: id.to_uppercase(),
+            accelerator: String::new(),
+            enabled: true,
+            checked: false,
+            submenu: Vec::new(),
+            id: id.to_string(),
+            name: String::new(),
+            uuid: None,
+        }
+    }
+
+    pub fn text(id: &str) -> ElectronMenuItem {
+        item(id, "normal")
+    }
+
+    pub fn separator() -> ElectronMenuItem {
+        item("", "separator")
+    }
+
+    pub fn submenu(id: &str, items: Vec<ElectronMenuItem>) -> ElectronMenuItem {
+        ElectronMenuItem {
+            submenu: items,
+            ..item(id, "submenu")
+        }
+    }
+
+    /// A radio item in the group named "group".
+    pub fn radio(id: &str, checked: bool) -> ElectronMenuItem {
+        ElectronMenuItem {
+            checked,
+            name: "group".to_string(),
+            ..item(id, "radio")
+        }
+    }
+
+    pub fn entries(items: &[ElectronMenuItem]) -> Vec<Node> {
+        items.iter().map(ElectronMenuItem::to_entry).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test_items::*, *};
+    use crate::document::parse_json;
+
+    /// Builds each item natively and describes it again, as from_menu_items does once the submenus are filled.
+    fn through_native(items: &[ElectronMenuItem]) -> Vec<ElectronMenuItem> {
+        items.iter().map(|item| ElectronMenuItem::from_native(&item.to_native(), through_native(&item.submenu))).collect()
+    }
+
+    fn without_positions(node: &Node) -> Node {
+        Node::new(match &node.value {
+            NodeValue::Array(items) => NodeValue::Array(items.iter().map(without_positions).collect()),
+            NodeValue::Object(entries) => NodeValue::Object(entries.iter().map(|(key, value)| (key.clone(), without_positions(value))).collect()),
+            value => value.clone(),
+        })
+    }
+
+    fn template(text: &str) -> Vec<Node> {
+        match parse_json(text).unwrap().value {
+            NodeValue::Array(nodes) => nodes,
+            _ => panic!("expected an array"),
+        }
+    }
+
+    #[test]
+    fn templates_survive_a_native_round_trip() {
+        let nodes = template(
+            r#"[
+                {"id": "file", "type": "submenu", "label": "File", "enabled": true, "submenu": [
+                    {"id": "open", "type": "normal", "label": "Open", "accelerator": "Ctrl+O", "enabled": true},
+                    {"type": "separator"},
+                    {"id": "recent", "type": "submenu", "label": "Recent", "enabled": false, "submenu": [
+                        {"id": "none", "type": "normal", "label": "None", "enabled": false}
+                    ]}
+                ]},
+                {"id": "wrap", "type": "checkbox", "label": "Wrap", "accelerator": "Alt+Z", "enabled": true, "checked": true},
+                {"id": "light", "type": "radio", "label": "Light", "enabled": true, "checked": false, "name": "theme"},
+                {"id": "dark", "type": "radio", "label": "Dark", "accelerator": "Ctrl+D", "enabled": false, "checked": true, "name": "theme"}
+            ]"#,
+        );
+        let mut diagnostics = Vec::new();
+        let items = ElectronMenuItem::from_nodes(&nodes, "template", &mut diagnostics);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+        let expected: Vec<_> = nodes.iter().map(without_positions).collect();
+        assert_eq!(entries(&through_native(&items)), expected);
+        assert_eq!(entries(&through_native(&through_native(&items))), expected);
+    }
+
+    #[test]
+    fn entries_leave_out_what_does_not_apply() {
+        let nodes = template(
+            r#"[
+                {"id": "rule", "type": "separator", "label": "Rule", "enabled": false},
+                {"type": "submenu", "label": "More", "accelerator": "Ctrl+M", "checked": true, "submenu": []},
+                {"label": "Plain", "checked": true, "name": "group"}
+            ]"#,
+        );
+        let mut diagnostics = Vec::new();
+        let items = ElectronMenuItem::from_nodes(&nodes, "template", &mut diagnostics);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+        let expected = template(
+            r#"[
+                {"type": "separator"},
+                {"type": "submenu", "label": "More", "enabled": true, "submenu": []},
+                {"type": "normal", "label": "Plain", "enabled": true}
+            ]"#,
+        );
+        assert_eq!(entries(&through_native(&items)), expected.iter().map(without_positions).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn uuid_accepts_the_u16_range_only() {
+        assert_eq!(to_uuid(0.0), Some(0));
+        assert_eq!(to_uuid(65535.0), Some(u16::MAX));
+        assert_eq!(to_uuid(65536.0), None);
+        assert_eq!(to_uuid(-1.0), None);
+        assert_eq!(to_uuid(1.5), None);
+        assert_eq!(to_uuid(f64::NAN), None);
+    }
+
+    #[test]
+    fn disabled_items_are_built_disabled() {
+        for itype in ["normal", "submenu", "checkbox", "radio"] {
+            let disabled = ElectronMenuItem {
+                enabled: false,
+                ..item("a", itype)
+            };
+            assert_eq!(disabled.disabled(), Some(true));
+            assert!(disabled.to_native().disabled, "{}", itype);
+            assert!(!item("a", itype).to_native().disabled, "{}", itype);
+        }
+    }
+
+    #[test]
+    fn items_keep_their_uuid_unless_they_have_none() {
+        let first = text("a").to_native();
+        let second = text("b").to_native();
+        assert_ne!(first.uuid, second.uuid);
+
+        let returned = ElectronMenuItem {
+            uuid: Some(first.uuid),
+            ..text("c")
+        }
+        .to_native();
+        assert_eq!(returned.uuid, first.uuid);
+    }
+
+    #[test]
+    fn described_items_are_built_again_with_their_uuids() {
+        // A menu is described this way before setConfig builds it again
+        let child = text("b").to_native();
+        let parent = item("s", "submenu").to_native();
+        let described = ElectronMenuItem::from_native(&parent, vec![ElectronMenuItem::from_native(&child, Vec::new())]);
+        assert_eq!(described.to_native().uuid, parent.uuid);
+        assert_eq!(described.submenu[0].to_native().uuid, child.uuid);
+    }
+
+    #[test]
+    fn depth_is_a_non_negative_integer_or_infinity() {
+        assert_eq!(to_depth(0.0), Some(0));
+        assert_eq!(to_depth(2.0), Some(2));
+        assert_eq!(to_depth(1e12), Some(u32::MAX));
+        assert_eq!(to_depth(f64::INFINITY), Some(u32::MAX));
+        assert_eq!(to_depth(-1.0), None);
+        assert_eq!(to_depth(1.5), None);
+        assert_eq!(to_depth(f64::NEG_INFINITY), None);
+        assert_eq!(to_depth(f64::NAN), None);
+    }
+
+    /// The paths of the fields the patch cannot change on items of the type.
+    fn unsupported(patch: &ItemPatch, itype: &str) -> Vec<String> {
+        match patch.check(itype) {
+            Ok(()) => Vec::new(),
+            Err(MenuError::InvalidTemplate(diagnostics)) => diagnostics.into_iter().map(|diagnostic| diagnostic.path).collect(),
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn patches_are_checked_against_the_item_type() {
+        let patch = ItemPatch {
+            label: Some("Label".to_string()),
+            enabled: Some(false),
+            checked: Some(true),
+            accelerator: Some("Ctrl+L".to_string()),
+            name: Some("group".to_string()),
+        };
+        assert_eq!(unsupported(&patch, "radio"), Vec::<String>::new());
+        assert_eq!(unsupported(&patch, "checkbox"), ["patch.name"]);
+        assert_eq!(unsupported(&patch, "normal"), ["patch.checked", "patch.name"]);
+        assert_eq!(unsupported(&patch, "submenu"), ["patch.accelerator", "patch.checked", "patch.name"]);
+        assert_eq!(unsupported(&patch, "separator"), ["patch.label", "patch.accelerator", "patch.checked", "patch.name"]);
+
+        let enabled = ItemPatch {
+            enabled: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(unsupported(&enabled, "separator"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn merge_changes_only_the_given_fields() {
+        let mut item = radio("a", false);
+        item.accelerator = "Ctrl+A".to_string();
+        let before = item.to_entry();
+        ItemPatch::default().merge(&mut item);
+        assert_eq!(item.to_entry(), before);
+
+        ItemPatch {
+            label: Some("Renamed".to_string()),
+            checked: Some(true),
+            name: Some("other".to_string()),
+            ..Default::default()
+        }
+        .merge(&mut item);
+        assert_eq!(item.label, "Renamed");
+        assert!(item.checked);
+        assert_eq!(item.name, "other");
+        assert!(item.enabled);
+        assert_eq!(item.accelerator, "Ctrl+A");
+    }
+
+    #[test]
+    fn checking_a_radio_item_unchecks_its_group() {
+        let other = ElectronMenuItem {
+            name: "other".to_string(),
+            ..radio("c", true)
+        };
+        let mut items = [radio("a", true), radio("b", false), other, text("d")];
+        let check = ItemPatch {
+            checked: Some(true),
+            ..Default::default()
+        };
+        check.merge_at(items.iter_mut(), 1);
+        let checked: Vec<_> = items.iter().map(|item| item.checked).collect();
+        assert_eq!(checked, [false, true, true, false]);
+
+        // Unchecking leaves the rest of the group alone
+        let uncheck = ItemPatch {
+            checked: Some(false),
+            ..Default::default()
+        };
+        uncheck.merge_at(items.iter_mut(), 1);
+        let checked: Vec<_> = items.iter().map(|item| item.checked).collect();
+        assert_eq!(checked, [false, false, true, false]);
+    }
+
+    #[test]
+    fn only_what_wcpopup_cannot_change_in_place_needs_a_replace() {
+        let mut item = text("a");
+        item.accelerator = "Ctrl+A".to_string();
+        let native = item.to_native();
+        let patch = |patch: ItemPatch| patch.needs_replace(&native);
+
+        assert!(!patch(ItemPatch {
+            label: Some("Renamed".to_string()),
+            enabled: Some(false),
+            ..Default::default()
+        }));
+        assert!(!patch(ItemPatch {
+            accelerator: Some("Ctrl+A".to_string()),
+            ..Default::default()
+        }));
+        assert!(patch(ItemPatch {
+            accelerator: Some("Ctrl+B".to_string()),
+            ..Default::default()
+        }));
+
+        let checked = radio("b", true).to_native();
+        let uncheck = ItemPatch {
+            checked: Some(false),
+            ..Default::default()
+        };
+        assert!(uncheck.needs_replace(&checked));
+        assert!(!uncheck.needs_replace(&radio("c", false).to_native()));
+        assert!(ItemPatch {
+            name: Some("other".to_string()),
+            ..Default::default()
+        }
+        .needs_replace(&checked));
+        assert!(!ItemPatch {
+            checked: Some(true),
+            name: Some("group".to_string()),
+            ..Default::default()
+        }
+        .needs_replace(&checked));
+    }
+
+    /// Reads the config over the default one, or returns the problems found in it.
+    fn read(text: &str, strict: bool) -> Result<Config, Vec<Diagnostic>> {
+        let mut config = Config::default();
+        match merge_node_config(&parse_json(text).unwrap(), &mut config, strict) {
+            Ok(()) => Ok(config),
+            Err(MenuError::InvalidConfig(diagnostics)) => Err(diagnostics),
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    fn paths(diagnostics: Vec<Diagnostic>) -> Vec<String> {
+        diagnostics.into_iter().map(|diagnostic| diagnostic.path).collect()
+    }
+
+    #[test]
+    fn config_colors_are_css_strings_or_native_numbers() {
+        let config = read(r##"{"color": {"dark": {"color": "#ff0000", "border": "rgba(0, 0, 255, 0.5)", "backgroundColor": 2105376}}}"##, true).unwrap();
+        let dark = &config.color.dark;
+        assert_eq!(dark.color, 0xff0000);
+        // Translucent colors with no red are given a red of 1
+        assert_eq!(dark.border, 0x0100ff80);
+        assert_eq!(dark.background_color, 0x202020);
+
+        let default = Config::default();
+        assert_eq!(dark.separator, default.color.dark.separator);
+        assert_eq!(config.color.light.color, default.color.light.color);
+    }
+
+    #[test]
+    fn invalid_colors_are_reported_even_when_not_strict() {
+        let diagnostics = read("{\"color\": {\n  \"light\": {\"hoverBackgroundColor\": \"nope\"}}}", false).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].path, "color.light.hoverBackgroundColor");
+        assert_eq!(diagnostics[0].message, "invalid color \"nope\"");
+        assert_eq!(diagnostics[0].position.map(|position| position.line), Some(2));
+
+        assert_eq!(paths(read(r#"{"size": 3, "color": {"dark": []}}"#, false).unwrap_err()), ["size", "color.dark"]);
+    }
+
+    #[test]
+    fn questionable_values_fall_back_unless_strict() {
+        let text = r#"{"theme": "sepia", "size": {"borderSize": -1, "submenuOffset": "far"}, "font": {"darkFontWeight": "Heavy", "lightFontSize": 0}, "corner": "Square", "color": {"dark": {"disabled": 1.5}}}"#;
+
+        let config = read(text, false).unwrap();
+        assert_eq!(config.theme, Theme::System);
+        assert_eq!(config.size.border_size, -1);
+        assert_eq!(config.size.submenu_offset, Config::default().size.submenu_offset);
+        assert_eq!(config.font.dark_font_weight, FontWeight::Normal);
+        assert_eq!(config.corner, Corner::DoNotRound);
+
+        assert_eq!(paths(read(text, true).unwrap_err()), ["theme", "size.borderSize", "size.submenuOffset", "font.darkFontWeight", "font.lightFontSize", "color.dark.disabled", "corner"]);
+    }
+}
@@ -40,7 +40,6 @@ var __importDefault = (this && this.__importDefault) || function (mod) {
 };
 Object.defineProperty(exports, "__esModule", { value: true });
 var electron_1 = require("electron");
-var os_1 = __importDefault(require("os"));
 var path_1 = __importDefault(require("path"));
 var index_1 = require("../lib/index");
 var menu;
@@ -58,28 +57,8 @@ var createWindow = function () {
     });
     win.loadFile("index.html");
     menu = new index_1.Menu();
-    var hbuf = win.getNativeWindowHandle();
-    var hwnd = 0;
-    if (os_1.default.platform() == "linux") {
-        if (os_1.default.endianness() == "LE") {
-            hwnd = hbuf.readUInt32LE();
-        }
-        else {
-            hwnd = hbuf.readUInt32BE();
-        }
-    }
-    else {
-        if (os_1.default.endianness() == "LE") {
-            hwnd = hbuf.readInt32LE();
-        }
-        else {
-            hwnd = hbuf.readInt32BE();
-        }
-    }
-    var config = (0, index_1.getDefaultConfig)();
-    console.log("node:".concat(config.color.dark.accelerator));
-    config.theme = "dark";
-    config.size.itemVerticalPadding = 10;
+    var hwnd = win.getNativeWindowHandle();
+    var config = { theme: "dark", size: { itemVerticalPadding: 10 } };
     menu.buildFromTemplateWithConfig(hwnd, getTemp(), config);
     var win2 = new electron_1.BrowserWindow({
         title: "sub",
@@ -92,14 +71,8 @@ var createWindow = function () {
     });
     win2.loadFile("index2.html");
     var menu2 = new index_1.Menu();
-    var hbuf2 = win2.getNativeWindowHandle();
-    if (os_1.default.endianness() == "LE") {
-        hwnd = hbuf2.readUInt32LE();
-    }
-    else {
-        hwnd = hbuf2.readUInt32BE();
-    }
-    menu2.buildFromTemplateWithConfig(hwnd, getTemp(), config);
+    var hwnd2 = win2.getNativeWindowHandle();
+    menu2.buildFromTemplateWithConfig(hwnd2, getTemp(), config);
 };
 var handleSetTitle = function (_event, pos) { return __awaiter(void 0, void 0, void 0, function () {
     return __generator(this, function (_a) {
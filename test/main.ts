@@ -1,7 +1,6 @@
 import { app, BrowserWindow, ipcMain, nativeTheme } from "electron";
-import os from "os";
 import path from "path";
-import { getDefaultConfig, Menu, MenuItem, MenuItemConstructorOptions } from "../lib/index";
+import { Menu, MenuItem, MenuItemConstructorOptions, PartialConfig } from "../lib/index";
 
 let menu: Menu;
 let dark = true;
@@ -21,26 +20,8 @@ const createWindow = () => {
     win.loadFile("index.html");
 
     menu = new Menu();
-    const hbuf = win.getNativeWindowHandle();
-    let hwnd = 0;
-
-    if (os.platform() == "linux") {
-        if (os.endianness() == "LE") {
-            hwnd = hbuf.readUInt32LE();
-        } else {
-            hwnd = hbuf.readUInt32BE();
-        }
-    } else {
-        if (os.endianness() == "LE") {
-            hwnd = hbuf.readInt32LE();
-        } else {
-            hwnd = hbuf.readInt32BE();
-        }
-    }
-    let config = getDefaultConfig();
-    console.log(`node:${config.color.dark.accelerator}`);
-    config.theme = "dark";
-    config.size.itemVerticalPadding = 10;
+    const hwnd = win.getNativeWindowHandle();
+    const config: PartialConfig = { theme: "dark", size: { itemVerticalPadding: 10 } };
     menu.buildFromTemplateWithConfig(hwnd, getTemp(), config);
 
     const win2 = new BrowserWindow({
@@ -55,15 +36,9 @@ const createWindow = () => {
 
     win2.loadFile("index2.html");
     const menu2 = new Menu();
-    const hbuf2 = win2.getNativeWindowHandle();
-
-    if (os.endianness() == "LE") {
-        hwnd = hbuf2.readUInt32LE();
-    } else {
-        hwnd = hbuf2.readUInt32BE();
-    }
+    const hwnd2 = win2.getNativeWindowHandle();
 
-    menu2.buildFromTemplateWithConfig(hwnd, getTemp(), config);
+    menu2.buildFromTemplateWithConfig(hwnd2, getTemp(), config);
 };
 
 const handleSetTitle = async (_event: any, pos: any) => {